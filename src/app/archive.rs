@@ -0,0 +1,327 @@
+use std::{
+    collections::HashSet,
+    fs::File,
+    io::{BufReader, Read, Write},
+    path::Path,
+};
+
+use tempfile::NamedTempFile;
+use zip::{CompressionMethod, ZipArchive, ZipWriter, write::SimpleFileOptions};
+
+use crate::cli::{Conv2JxlArgs, DetectMethod, FileType};
+
+/// Result of converting the image entries of an archive into a new archive.
+pub struct ConvertedArchive {
+    /// The new archive, next to the original so it can replace it
+    pub file: NamedTempFile,
+    /// Number of entries converted to JPEG XL
+    pub converted: usize,
+    /// Number of image entries that failed to convert, and were kept as they were
+    pub failed: usize,
+    /// Error for the first entry that failed to convert
+    pub first_error: Option<String>,
+}
+
+/// Number of bytes read from the start of an entry to detect its type by content.
+const SNIFF_LEN: u64 = 512;
+
+/// Returns true if entries of type `ftype` should be converted: those given with --ext,
+/// or any image if archives are the only type given, as with `--ext zip`.
+fn wanted(ftype: FileType, args: &Conv2JxlArgs) -> bool {
+    match args.extensions.iter().all(|&t| t == FileType::ZIP) {
+        true => !matches!(ftype, FileType::ZIP | FileType::JXL),
+        false => args.extensions.contains(&ftype),
+    }
+}
+
+/// Determines the type to convert an archive entry as from its name and the first bytes of its content,
+/// and the type implied by its name, like for files found by scanning. Nested archives and JPEG XL entries
+/// are left alone. Content is only looked at with --detect content.
+fn entry_type(name: &str, header: &[u8], args: &Conv2JxlArgs) -> Option<(FileType, Option<FileType>)> {
+    let named = named_type(name, args);
+
+    let ftype = match args.detect {
+        DetectMethod::Extension => named?,
+        DetectMethod::Content => match super::detect::sniff_bytes(header) {
+            Some(sniffed) => match named {
+                Some(named) if named.is_compatible(sniffed) => named,
+                _ => sniffed,
+            },
+            None => named?,
+        },
+    };
+
+    match ftype {
+        FileType::ZIP | FileType::JXL => None,
+        _ => wanted(ftype, args).then_some((ftype, named)),
+    }
+}
+
+fn named_type(name: &str, args: &Conv2JxlArgs) -> Option<FileType> {
+    Path::new(name)
+        .extension()
+        .and_then(|ext| ext.to_str())
+        .and_then(|ext| args.ext_map.get(ext))
+}
+
+/// Name of the converted entry, following the same naming as converted files.
+fn output_name(name: &str, named: FileType, no_preserve_extension: bool) -> String {
+    let path = match no_preserve_extension {
+        false => Path::new(name).with_extension(format!("{named}.jxl")),
+        true => Path::new(name).with_extension("jxl"),
+    };
+
+    path.to_string_lossy().into_owned()
+}
+
+/// Converts a single entry with `cjxl`, returning the JPEG XL data,
+/// or `None` if it isn't any smaller than the `stored` size of the entry.
+///
+/// The entry is streamed to a temporary file for `cjxl`, after the `header` already read from it,
+/// so it is never held in memory whole, whatever size its header claims.
+fn convert_entry(
+    header: &[u8],
+    rest: &mut impl Read,
+    stored: u64,
+    ftype: FileType,
+    args: &Conv2JxlArgs,
+    quality: u8,
+) -> Result<Option<Vec<u8>>, Box<dyn std::error::Error>> {
+    let mut input = tempfile::Builder::new().suffix(&format!(".{ftype}")).tempfile()?;
+    input.write_all(header)?;
+    std::io::copy(rest, &mut input)?;
+    input.flush()?;
+
+    let png = match ftype.needs_conversion() {
+        true => Some(super::conv2png::conv2png(input.path(), ftype)?),
+        false => None,
+    };
+
+    let output = tempfile::Builder::new().suffix(".jxl").tempfile()?;
+
+    let quality = match args.lossless_jpeg && ftype == FileType::JPEG {
+        true => 100,
+        false => quality,
+    };
+
+    let result = super::convert::cjxl_command(
+        args,
+        quality,
+        png.as_ref().map_or(input.path(), |png| png.path()),
+        output.path(),
+    )
+    .output()?;
+
+    if !result.status.success() {
+        return Err(format!(
+            "Conversion command failed with {}: {}",
+            result.status,
+            String::from_utf8_lossy(&result.stderr).trim()
+        )
+        .into());
+    }
+
+    let jxl = std::fs::read(output.path())?;
+
+    if jxl.is_empty() {
+        return Err("Conversion produced an empty file".into());
+    }
+
+    Ok(((jxl.len() as u64) < stored).then_some(jxl))
+}
+
+/// Converts the image entries of the archive at `path` into a new archive with `.jxl` entries,
+/// copying every other entry untouched. Entries that don't get any smaller are kept as they were.
+pub fn convert_archive(
+    path: &Path,
+    args: &Conv2JxlArgs,
+    quality: u8,
+) -> Result<ConvertedArchive, Box<dyn std::error::Error>> {
+    let mut archive = ZipArchive::new(BufReader::new(File::open(path)?))?;
+
+    let dir = path.parent().filter(|dir| !dir.as_os_str().is_empty());
+    let file = NamedTempFile::new_in(dir.unwrap_or(Path::new(".")))?;
+
+    let mut writer = ZipWriter::new(file.reopen()?);
+
+    // converted entries can't take the name of an entry already there
+    let mut names = HashSet::<String>::from_iter(archive.file_names().map(str::to_owned));
+
+    let (mut converted, mut failed, mut first_error) = (0, 0, None);
+
+    for i in 0..archive.len() {
+        let mut jxl = None;
+
+        {
+            let mut entry = archive.by_index(i)?;
+
+            // by name alone, nothing has to be read from entries that aren't converted
+            let maybe_image = match args.detect {
+                DetectMethod::Extension => named_type(entry.name(), args).is_some_and(|ftype| wanted(ftype, args)),
+                DetectMethod::Content => true,
+            };
+
+            if maybe_image && entry.is_file() && !entry.encrypted() {
+                let name = entry.name().to_owned();
+
+                let mut header = Vec::new();
+
+                let sniffed = match args.detect {
+                    DetectMethod::Content => (&mut entry).take(SNIFF_LEN).read_to_end(&mut header).map(|_| ()),
+                    DetectMethod::Extension => Ok(()),
+                };
+
+                // an entry that can't be read is kept as it was, like one that fails to convert
+                if let Err(e) = sniffed {
+                    failed += 1;
+                    first_error.get_or_insert_with(|| format!("'{name}': {e}"));
+                } else if let Some((ftype, named)) = entry_type(&name, &header, args) {
+                    let output = output_name(&name, named.unwrap_or(ftype), args.no_preserve_extension);
+                    let stored = entry.compressed_size();
+
+                    if !names.contains(&output) {
+                        match convert_entry(&header, &mut entry, stored, ftype, args, quality) {
+                            Ok(Some(data)) => jxl = Some((output, data)),
+                            Ok(None) => {}
+                            Err(e) => {
+                                failed += 1;
+                                first_error.get_or_insert_with(|| format!("'{name}': {e}"));
+                            }
+                        }
+                    }
+                }
+            }
+        }
+
+        let entry = archive.by_index_raw(i)?;
+
+        let Some((output, data)) = jxl else {
+            writer.raw_copy_file(entry)?;
+            continue;
+        };
+
+        // JPEG XL is already compressed, so compressing again would only cost time
+        let mut options = SimpleFileOptions::default()
+            .compression_method(CompressionMethod::Stored)
+            .large_file(data.len() as u64 >= u32::MAX as u64);
+
+        if let Some(time) = entry.last_modified() {
+            options = options.last_modified_time(time);
+        }
+
+        if let Some(mode) = entry.unix_mode() {
+            options = options.unix_permissions(mode);
+        }
+
+        drop(entry);
+
+        writer.start_file(&output, options)?;
+        writer.write_all(&data)?;
+
+        names.insert(output);
+        converted += 1;
+    }
+
+    writer.set_raw_comment(archive.comment().into())?;
+    writer.finish()?;
+
+    Ok(ConvertedArchive {
+        file,
+        converted,
+        failed,
+        first_error,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(args: &[&str]) -> Conv2JxlArgs {
+        let mut args: Conv2JxlArgs = argh::FromArgs::from_args(&["conv2jxl"], args).unwrap();
+        args.normalize();
+        args
+    }
+
+    const PNG: &[u8] = b"\x89PNG\x0d\x0a\x1a\x0a";
+
+    #[test]
+    fn only_zip_means_every_image() {
+        let zip = args(&["--ext", "zip"]);
+
+        assert_eq!(
+            entry_type("a.png", &[], &zip),
+            Some((FileType::PNG, Some(FileType::PNG)))
+        );
+        assert_eq!(
+            entry_type("b.jpg", &[], &zip),
+            Some((FileType::JPEG, Some(FileType::JPEG)))
+        );
+        assert_eq!(entry_type("c.jxl", &[], &zip), None);
+        assert_eq!(entry_type("d.zip", &[], &zip), None);
+        assert_eq!(entry_type("e.txt", &[], &zip), None);
+
+        let zip_png = args(&["--ext", "zip,png"]);
+
+        assert!(entry_type("a.png", &[], &zip_png).is_some());
+        assert_eq!(entry_type("b.jpg", &[], &zip_png), None);
+    }
+
+    #[test]
+    fn detection() {
+        let by_name = args(&["--ext", "zip,png"]);
+
+        // the content isn't looked at by name
+        assert!(entry_type("a.png", b"not a png", &by_name).is_some());
+        assert_eq!(entry_type("a.dat", PNG, &by_name), None);
+
+        let by_content = args(&["--ext", "zip,png", "--detect", "content"]);
+
+        assert_eq!(entry_type("a.dat", PNG, &by_content), Some((FileType::PNG, None)));
+        assert_eq!(
+            entry_type("a.jpg", PNG, &by_content),
+            Some((FileType::PNG, Some(FileType::JPEG)))
+        );
+        assert_eq!(entry_type("a.png", b"\xff\xd8\xff\xe0", &by_content), None);
+    }
+
+    #[test]
+    fn unreadable_entry_is_kept() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("a.zip");
+
+        let mut writer = ZipWriter::new(File::create(&path).unwrap());
+        let stored = SimpleFileOptions::default().compression_method(CompressionMethod::Stored);
+        writer.start_file("a.png", stored).unwrap();
+        writer.write_all(&[PNG, b"image data"].concat()).unwrap();
+        writer.finish().unwrap();
+
+        // corrupt the entry, so its checksum no longer matches
+        let mut data = std::fs::read(&path).unwrap();
+        let at = data.windows(10).position(|window| window == b"image data").unwrap();
+        data[at] ^= 0xff;
+        std::fs::write(&path, data).unwrap();
+
+        let archive = convert_archive(&path, &args(&["--ext", "zip", "--detect", "content"]), 90).unwrap();
+
+        assert_eq!((archive.converted, archive.failed), (0, 1));
+        assert!(archive.first_error.unwrap().starts_with("'a.png': "));
+    }
+
+    #[test]
+    fn nothing_to_convert() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("a.zip");
+
+        let mut writer = ZipWriter::new(File::create(&path).unwrap());
+        writer.start_file("readme.txt", SimpleFileOptions::default()).unwrap();
+        writer.write_all(b"no images here").unwrap();
+        writer.finish().unwrap();
+
+        for detect in ["extension", "content"] {
+            let archive = convert_archive(&path, &args(&["--ext", "zip", "--detect", detect]), 90).unwrap();
+
+            assert_eq!((archive.converted, archive.failed), (0, 0));
+        }
+    }
+}
@@ -42,3 +42,21 @@ pub fn conv2png(path: &Path, ext: FileType) -> Result<NamedTempFile, Box<dyn std
 
     Ok(tmp)
 }
+
+/// Converts the PNG at `png` to a file of type `ext` at `output`, the reverse of [`conv2png`],
+/// for the types `djxl` can't write itself.
+pub fn png2(png: &Path, ext: FileType, output: &Path) -> Result<(), Box<dyn std::error::Error>> {
+    let format = match ext {
+        FileType::TIFF => image::ImageFormat::Tiff,
+        FileType::TGA => image::ImageFormat::Tga,
+        FileType::QOI => image::ImageFormat::Qoi,
+        FileType::BMP => image::ImageFormat::Bmp,
+        _ => return Err(format!("Unsupported file type for conversion from PNG: {:?}", ext).into()),
+    };
+
+    let decoder = image::codecs::png::PngDecoder::new(BufReader::new(File::open(png)?))?;
+
+    image::DynamicImage::from_decoder(decoder)?.save_with_format(output, format)?;
+
+    Ok(())
+}
@@ -1,6 +1,8 @@
+#[cfg(windows)]
 use std::os::windows::fs::FileTimesExt as _;
 
-use crate::cli::Conv2JxlArgs;
+use super::journal::{JournalOutcome, JournalRecord};
+use crate::cli::{ByteSize, Conv2JxlArgs, HumanDuration};
 
 use super::*;
 
@@ -29,7 +31,10 @@ impl SharedState {
 
 impl ConversionState {
     pub fn completed(&self) -> bool {
-        self.idx.load(Ordering::Relaxed) >= self.files.len()
+        (self.stopping.load(Ordering::Relaxed)
+            || self.scan_complete.load(Ordering::Acquire)
+                && !self.watching.load(Ordering::Acquire)
+                && self.idx.load(Ordering::Relaxed) >= self.files.len())
             && self
                 .active
                 .iter()
@@ -37,9 +42,59 @@ impl ConversionState {
     }
 
     pub fn stop(&self) {
+        self.stopping.store(true, Ordering::Relaxed);
         self.idx.store(self.files.len(), Ordering::Relaxed);
     }
 
+    /// Waits until the file at index `i` has been found, returning false if it never will be.
+    pub fn wait_for_file(&self, i: usize) -> bool {
+        loop {
+            if self.stopping.load(Ordering::Relaxed) {
+                return false;
+            }
+
+            if i < self.files.len() {
+                return true;
+            }
+
+            // the scan may have pushed more files before completing
+            if self.scan_complete.load(Ordering::Acquire) && !self.watching.load(Ordering::Acquire) {
+                return i < self.files.len();
+            }
+
+            std::thread::sleep(std::time::Duration::from_millis(10));
+        }
+    }
+
+    /// Checks whether handing out the file at index `i` would go over --time-budget or --byte-budget,
+    /// claiming its bytes otherwise. They are given back with [`Self::release_budget`] if it isn't converted.
+    fn over_budget(&self, i: usize, args: &Conv2JxlArgs, program_start: Instant) -> Option<StopReason> {
+        if let Some(HumanDuration(budget)) = args.time_budget
+            && program_start.elapsed() >= budget
+        {
+            return Some(StopReason::TimeBudget);
+        }
+
+        if let Some(ByteSize(budget)) = args.byte_budget {
+            let size = self.files[i].metadata.len();
+            let claimed = self.claimed_bytes.fetch_add(size, Ordering::Relaxed) + size;
+
+            if claimed > budget {
+                self.claimed_bytes.fetch_sub(size, Ordering::Relaxed);
+                return Some(StopReason::ByteBudget);
+            }
+        }
+
+        None
+    }
+
+    /// Gives back the bytes claimed for `src`, for a file that never went to `cjxl`.
+    fn release_budget(&self, src: &FileEntry, args: &Conv2JxlArgs) {
+        if args.byte_budget.is_some() {
+            self.claimed_bytes.fetch_sub(src.metadata.len(), Ordering::Relaxed);
+        }
+    }
+
     pub fn add_error(&self, idx: usize, last_active: u64) {
         let src = &self.files[idx];
         self.progress.get(src.ext).errored(src.metadata.len());
@@ -66,6 +121,18 @@ impl ConversionState {
     pub fn next_file(&self, thread_idx: usize, args: &Conv2JxlArgs, program_start: Instant, stop: &mut bool) {
         let i = self.idx.fetch_add(1, Ordering::Relaxed);
 
+        if !self.wait_for_file(i) {
+            *stop = true;
+            return;
+        }
+
+        if let Some(reason) = self.over_budget(i, args, program_start) {
+            let _ = self.stop_reason.set(reason);
+            self.stop();
+            *stop = true;
+            return;
+        }
+
         // set active thread idx
         let thread = &self.active[thread_idx];
         thread.file_idx.store(i, Ordering::Relaxed);
@@ -73,15 +140,31 @@ impl ConversionState {
             .start_time
             .store(program_start.elapsed().as_millis() as u64, Ordering::Relaxed);
 
-        if i >= self.files.len() {
-            *stop = true;
+        let src = &self.files[i];
+
+        let journal = self.journal.as_ref();
+        let hash = journal.and_then(|journal| journal.hash(src));
+
+        if let Some(record) = journal.and_then(|journal| journal.known_outcome(src, hash.as_deref())) {
+            self.release_budget(src, args);
+            self.replay(i, src, record, program_start);
             return;
         }
 
-        let src = &self.files[i];
+        if let Some(journal) = journal
+            && !args.dry_run
+        {
+            let output_path = src.output_path(args.no_preserve_extension);
+
+            // an output left by a run that was killed mid-conversion may be incomplete, so it's converted again
+            if journal.interrupted(src, hash.as_deref()) && output_path != src.path {
+                let _ = std::fs::remove_file(&output_path);
+            }
+        }
 
         let mut quality = args.quality;
-        let mut inefficient = false;
+        let conv_start = Instant::now();
+        let mut attempt = Attempt::default();
         let mut tries = 0;
 
         let lossless_jpeg = args.lossless_jpeg && src.ext == FileType::JPEG;
@@ -94,11 +177,15 @@ impl ConversionState {
                 quality = 100; // force lossless for JPEG files
             }
 
-            self.next(i, src, args, program_start, quality, &mut inefficient);
+            attempt.quality = quality;
+            attempt.status = None;
+            attempt.stderr = None;
+
+            self.next(i, src, args, program_start, hash.as_deref(), &mut attempt);
 
             // if it's not inefficient, or if lossless_jpeg is enabled (which forces quality 100),
             // we don't need to try again
-            if !inefficient || lossless_jpeg {
+            if !attempt.inefficient || lossless_jpeg {
                 break;
             }
 
@@ -114,51 +201,127 @@ impl ConversionState {
 
             quality = quality_if_inefficient;
         }
+
+        let _ = src.quality.set(attempt.quality);
+        src.elapsed
+            .store(conv_start.elapsed().as_millis() as u64, Ordering::Relaxed);
+
+        self.settle_inefficient(i, src, &attempt, program_start);
+
+        if args.dry_run {
+            return;
+        }
+
+        let (outcome, sizes, message) = match src.state.get() {
+            Some(ConversionOutcome::Success(input, output)) => {
+                (JournalOutcome::Converted, Some((*input, *output)), None)
+            }
+            Some(ConversionOutcome::Warning(input, output, warning)) => (
+                JournalOutcome::Converted,
+                Some((*input, *output)),
+                Some(warning.to_string()),
+            ),
+            Some(ConversionOutcome::Skipped) => (JournalOutcome::Skipped, None, None),
+            Some(ConversionOutcome::Inefficient(input, output)) => {
+                (JournalOutcome::Inefficient, Some((*input, *output)), None)
+            }
+            Some(ConversionOutcome::Error(e)) => (JournalOutcome::Failed, None, Some(e.to_string())),
+            None => (JournalOutcome::Inefficient, attempt.sizes, None),
+        };
+
+        if let Some(ref error_log) = self.error_log
+            && let Err(e) = error_log.log(src, args, outcome, sizes, message.as_deref(), &attempt)
+        {
+            let _ = self.log_error.set(format!("Error log failed: {e}"));
+        }
+
+        if journal.is_some() {
+            self.journal_record(src, hash, outcome, sizes, message);
+        }
+    }
+
+    /// Records `src` as inefficient if the last attempt was, and no lower quality was left to try.
+    /// Only a retry that is inefficient as well sets the outcome itself.
+    fn settle_inefficient(&self, i: usize, src: &FileEntry, attempt: &Attempt, program_start: Instant) {
+        if src.state.get().is_some() {
+            return;
+        }
+
+        if let Some((input, output)) = attempt.sizes.filter(|_| attempt.inefficient) {
+            let last_active = src.set_state(program_start, ConversionOutcome::Inefficient(input, output));
+
+            self.add_inefficient(i, last_active);
+        }
+    }
+
+    /// Appends to the journal, keeping the first error writing to it.
+    fn journal_record(
+        &self,
+        src: &FileEntry,
+        hash: Option<String>,
+        outcome: JournalOutcome,
+        sizes: Option<(u64, u64)>,
+        message: Option<String>,
+    ) {
+        if let Some(ref journal) = self.journal
+            && let Err(e) = journal.record(src, hash, outcome, sizes, message)
+        {
+            let _ = self.log_error.set(format!("Journal failed: {e}"));
+        }
     }
 
+    /// Sets the outcome an earlier run recorded in the journal for `src`, instead of converting it again.
+    fn replay(&self, i: usize, src: &FileEntry, record: &JournalRecord, program_start: Instant) {
+        let input = record.input.unwrap_or(src.metadata.len());
+
+        if record.outcome == JournalOutcome::Failed {
+            let message = record.message.as_deref().unwrap_or("unknown error");
+            let last_active = src.set_state(
+                program_start,
+                ConversionOutcome::Error(format!("Failed in an earlier run with the same settings: {message}").into()),
+            );
+
+            self.add_error(i, last_active);
+        } else {
+            let last_active = src.set_state(
+                program_start,
+                ConversionOutcome::Inefficient(input, record.output.unwrap_or(input)),
+            );
+
+            self.add_inefficient(i, last_active);
+        }
+    }
+
+    /// Converts `src` with `cjxl`. `hash` is its content hash for the journal, with --journal-hash.
     pub fn next(
         &self,
         i: usize,
         src: &FileEntry,
         args: &Conv2JxlArgs,
         program_start: Instant,
-        quality: u8,
-        inefficient: &mut bool,
+        hash: Option<&str>,
+        attempt: &mut Attempt,
     ) {
         self.wait_paused();
 
+        if src.ext == FileType::ZIP {
+            return self.next_archive(i, src, args, program_start, hash, attempt);
+        }
+
+        let quality = attempt.quality;
+
         let conv_start = Instant::now();
 
-        let output_path = match args.no_preserve_extension {
-            false => src.path.with_extension(format!("{}.jxl", src.ext)),
-            true => src.path.with_extension("jxl"),
-        };
+        let output_path = src.output_path(args.no_preserve_extension);
 
         if output_path.exists() && !args.overwrite {
+            self.release_budget(src, args);
+
             let last_active = src.set_state(program_start, ConversionOutcome::Skipped);
             self.non_success.write().unwrap().insert((Reverse(last_active), i)); // skipped files are considered non-success for UI purposes
             return;
         }
 
-        if args.min_width > 0 || args.min_height > 0 || args.max_width < u32::MAX || args.max_height < u32::MAX {
-            let Ok(dimensions) = imagesize::size(&src.path) else {
-                let last_active = src.set_state(
-                    program_start,
-                    ConversionOutcome::Error("Failed to read image dimensions.".into()),
-                );
-                self.add_error(i, last_active);
-                return;
-            };
-
-            if !args.width().contains(&(dimensions.width as u32))
-                || !args.height().contains(&(dimensions.height as u32))
-            {
-                let last_active = src.set_state(program_start, ConversionOutcome::Skipped);
-                self.non_success.write().unwrap().insert((Reverse(last_active), i)); // skipped files are considered non-success for UI purposes
-                return;
-            }
-        }
-
         let mut tmp_file = None;
 
         if src.ext.needs_conversion() {
@@ -177,26 +340,15 @@ impl ConversionState {
             };
         }
 
-        let mut cmd = std::process::Command::new("cjxl");
-
-        cmd.arg(match tmp_file {
-            Some(ref tmp) => tmp.path(),
-            None => &src.path,
-        })
-        .arg(&output_path);
-
-        cmd.arg("-q").arg(quality.to_string());
-        cmd.arg("-e").arg(args.effort.to_string());
-        cmd.arg("--num_threads").arg(args.threads.to_string());
-        cmd.arg("--lossless_jpeg")
-            .arg(if args.lossless_jpeg { "1" } else { "0" });
-        cmd.arg("--quiet");
-
-        if args.disable_jpeg_reconstruction {
-            cmd.arg("--allow_expert_options")
-                .arg("--allow_jpeg_reconstruction")
-                .arg("0");
-        }
+        let mut cmd = cjxl_command(
+            args,
+            quality,
+            match tmp_file {
+                Some(ref tmp) => tmp.path(),
+                None => &src.path,
+            },
+            &output_path,
+        );
 
         let input = src.metadata.len();
 
@@ -206,8 +358,16 @@ impl ConversionState {
             return;
         }
 
+        // only recorded now, so that an output that was already there is never taken for an interrupted one
+        self.journal_record(src, hash.map(str::to_owned), JournalOutcome::Started, None, None);
+
         let output = cmd.output();
 
+        if let Ok(ref output) = output {
+            attempt.status = Some(output.status);
+            attempt.stderr = Some(String::from_utf8_lossy(&output.stderr).into_owned());
+        }
+
         drop(tmp_file); // ensure temporary file is deleted after conversion
 
         let _output = match output {
@@ -327,32 +487,73 @@ impl ConversionState {
                 return;
             }
 
-            if *inefficient {
+            if attempt.inefficient {
                 let last_active = src.set_state(program_start, ConversionOutcome::Inefficient(input, output));
 
                 self.add_inefficient(i, last_active);
             }
 
-            *inefficient = true;
+            attempt.inefficient = true;
+            attempt.sizes = Some((input, output));
 
             return;
         }
 
-        let mut warning = inefficient.then_some(Cow::Borrowed("Used lower quality due to inefficiency"));
-        let mut times = None;
+        let mut warning = attempt
+            .inefficient
+            .then_some(Cow::Borrowed("Used lower quality due to inefficiency"));
 
-        if let (Ok(ctime), Ok(mtime), Ok(atime)) =
-            (src.metadata.created(), src.metadata.modified(), src.metadata.accessed())
+        if let Some(named) = src.mismatch() {
+            let mismatch = format!("Extension '{named}' does not match detected content '{}'", src.ext);
+
+            add_warning(&mut warning, mismatch);
+        }
+
+        let times = file_times(&src.metadata);
+
+        if let Some(times) = times
+            && let Err(e) = file.set_times(times)
         {
-            times = Some(
-                std::fs::FileTimes::new()
-                    .set_created(ctime)
-                    .set_modified(mtime)
-                    .set_accessed(atime),
-            );
+            add_warning(&mut warning, format!("Failed to set file times: {e}"));
+        }
+
+        // give every other link to the source its own link to the output, so the link set survives conversion
+        for link in &src.links {
+            let link_output = src.output_path_for(link, args.no_preserve_extension);
+
+            if link_output.exists() {
+                if !args.overwrite {
+                    add_warning(
+                        &mut warning,
+                        format!("Output for link '{}' already exists", link.display()),
+                    );
+                    continue;
+                }
+
+                if let Err(e) = std::fs::remove_file(&link_output) {
+                    add_warning(
+                        &mut warning,
+                        format!("Failed to replace output for link '{}': {e}", link.display()),
+                    );
+                    continue;
+                }
+            }
 
-            if let Err(e) = file.set_times(times.unwrap()) {
-                warning = Some(format!("Failed to set file times: {e}").into());
+            if let Err(e) = std::fs::hard_link(&output_path, &link_output) {
+                add_warning(
+                    &mut warning,
+                    format!("Failed to link output for '{}': {e}", link.display()),
+                );
+                continue;
+            }
+
+            // truncating the source already truncated every link, as they share the same data
+            if args.delete
+                && !args.truncate
+                && *link != link_output
+                && let Err(e) = std::fs::remove_file(link)
+            {
+                add_warning(&mut warning, format!("Failed to delete link '{}': {e}", link.display()));
             }
         }
 
@@ -363,11 +564,14 @@ impl ConversionState {
                 // some users' workflows
                 match std::fs::OpenOptions::new().write(true).truncate(true).open(&src.path) {
                     Err(e) => {
-                        warning = Some(format!("Failed to open source file for truncation: {e}").into());
+                        add_warning(&mut warning, format!("Failed to open source file for truncation: {e}"));
                     }
                     Ok(f) if times.is_some() => {
                         if let Err(e) = f.set_times(times.unwrap()) {
-                            warning = Some(format!("Failed to set file times on truncated source file: {e}").into());
+                            add_warning(
+                                &mut warning,
+                                format!("Failed to set file times on truncated source file: {e}"),
+                            );
                         }
                     }
                     Ok(_) => { /* success truncating file */ }
@@ -375,7 +579,7 @@ impl ConversionState {
             } else if args.delete
                 && let Err(e) = std::fs::remove_file(&src.path)
             {
-                warning = Some(format!("Failed to delete source file: {e}").into());
+                add_warning(&mut warning, format!("Failed to delete source file: {e}"));
             }
         }
 
@@ -397,4 +601,333 @@ impl ConversionState {
             .get(src.ext)
             .add(input, output, conv_start.elapsed().as_millis() as u64);
     }
+
+    /// Converts the images inside an archive into a new archive, which replaces the original
+    /// if it is smaller by at least --min-ratio.
+    fn next_archive(
+        &self,
+        i: usize,
+        src: &FileEntry,
+        args: &Conv2JxlArgs,
+        program_start: Instant,
+        hash: Option<&str>,
+        attempt: &mut Attempt,
+    ) {
+        let conv_start = Instant::now();
+        let quality = attempt.quality;
+
+        let input = src.metadata.len();
+
+        if args.dry_run {
+            src.set_state(program_start, ConversionOutcome::Success(input, input));
+            return;
+        }
+
+        self.journal_record(src, hash.map(str::to_owned), JournalOutcome::Started, None, None);
+
+        let archive = match super::archive::convert_archive(&src.path, args, quality) {
+            Ok(archive) => archive,
+            Err(e) => {
+                let last_active = src.set_state(
+                    program_start,
+                    ConversionOutcome::Error(format!("Failed to convert archive: {e}").into()),
+                );
+
+                self.add_error(i, last_active);
+
+                return;
+            }
+        };
+
+        if archive.converted == 0 && archive.failed > 0 {
+            let last_active = src.set_state(
+                program_start,
+                ConversionOutcome::Error(
+                    format!(
+                        "None of the images in the archive could be converted, first error: {}",
+                        archive.first_error.unwrap_or_default()
+                    )
+                    .into(),
+                ),
+            );
+
+            self.add_error(i, last_active);
+
+            return;
+        }
+
+        // nothing to convert, or nothing got smaller, so the archive is left as it was
+        if archive.converted == 0 {
+            let last_active = src.set_state(program_start, ConversionOutcome::Skipped);
+            self.non_success.write().unwrap().insert((Reverse(last_active), i));
+            return;
+        }
+
+        let output = match archive.file.as_file().metadata() {
+            Ok(meta) => meta.len(),
+            Err(e) => {
+                let last_active = src.set_state(
+                    program_start,
+                    ConversionOutcome::Error(format!("Failed to get metadata for converted archive: {e}").into()),
+                );
+
+                self.add_error(i, last_active);
+
+                return;
+            }
+        };
+
+        let ratio = output as f32 / input as f32;
+
+        // the new archive is a temporary file, so it is deleted when dropped here
+        if ratio > args.min_ratio {
+            if attempt.inefficient {
+                let last_active = src.set_state(program_start, ConversionOutcome::Inefficient(input, output));
+
+                self.add_inefficient(i, last_active);
+            }
+
+            attempt.inefficient = true;
+            attempt.sizes = Some((input, output));
+
+            return;
+        }
+
+        let mut warning = attempt
+            .inefficient
+            .then_some(Cow::Borrowed("Used lower quality due to inefficiency"));
+
+        if let Some(ref e) = archive.first_error {
+            add_warning(
+                &mut warning,
+                format!(
+                    "{} images failed to convert and were kept as they were, first error: {e}",
+                    archive.failed
+                ),
+            );
+        }
+
+        // the new archive takes the place of the original, so it keeps its permissions and times
+        if let Err(e) = archive.file.as_file().set_permissions(src.metadata.permissions()) {
+            add_warning(&mut warning, format!("Failed to set permissions: {e}"));
+        }
+
+        if let Some(times) = file_times(&src.metadata)
+            && let Err(e) = archive.file.as_file().set_times(times)
+        {
+            add_warning(&mut warning, format!("Failed to set file times: {e}"));
+        }
+
+        if let Err(e) = archive.file.persist(&src.path) {
+            let last_active = src.set_state(
+                program_start,
+                ConversionOutcome::Error(format!("Failed to replace archive: {}", e.error).into()),
+            );
+
+            self.add_error(i, last_active);
+
+            return;
+        }
+
+        let is_warning = warning.is_some();
+
+        let last_active = src.set_state(
+            program_start,
+            match warning {
+                Some(w) => ConversionOutcome::Warning(input, output, w),
+                None => ConversionOutcome::Success(input, output),
+            },
+        );
+
+        if is_warning {
+            self.non_success.write().unwrap().insert((Reverse(last_active), i));
+        }
+
+        self.progress
+            .get(src.ext)
+            .add(input, output, conv_start.elapsed().as_millis() as u64);
+    }
+}
+
+/// Details of the latest attempt at converting a file, beyond its outcome.
+#[derive(Debug, Default)]
+pub struct Attempt {
+    pub quality: u8,
+    /// Set once an attempt produced an output that was too large
+    pub inefficient: bool,
+    /// Input and output size of the inefficient attempt
+    pub sizes: Option<(u64, u64)>,
+    /// Exit status of `cjxl`, if it ran
+    pub status: Option<std::process::ExitStatus>,
+    pub stderr: Option<String>,
+}
+
+/// Adds `message` to the warning for a file, after the ones it already has.
+fn add_warning(warning: &mut Option<Cow<'static, str>>, message: impl Into<Cow<'static, str>>) {
+    let message = message.into();
+
+    *warning = Some(match warning.take() {
+        Some(warning) => format!("{warning}. {message}").into(),
+        None => message,
+    });
+}
+
+/// Times to give a file taking the place of the one `metadata` is for: its modification and access times,
+/// and its creation time on Windows, which is the only platform where it can be set.
+pub fn file_times(metadata: &std::fs::Metadata) -> Option<std::fs::FileTimes> {
+    let (Ok(mtime), Ok(atime)) = (metadata.modified(), metadata.accessed()) else {
+        return None;
+    };
+
+    let times = std::fs::FileTimes::new().set_modified(mtime).set_accessed(atime);
+
+    #[cfg(windows)]
+    let times = match metadata.created() {
+        Ok(ctime) => times.set_created(ctime),
+        Err(_) => times,
+    };
+
+    Some(times)
+}
+
+/// Builds the `cjxl` command converting `input` to `output` with the given quality and the other encoder options.
+pub fn cjxl_command(args: &Conv2JxlArgs, quality: u8, input: &Path, output: &Path) -> std::process::Command {
+    let mut cmd = std::process::Command::new("cjxl");
+
+    cmd.arg(input).arg(output);
+
+    cmd.arg("-q").arg(quality.to_string());
+    cmd.arg("-e").arg(args.effort.to_string());
+    cmd.arg("--num_threads").arg(args.threads.to_string());
+    cmd.arg("--lossless_jpeg")
+        .arg(if args.lossless_jpeg { "1" } else { "0" });
+    cmd.arg("--quiet");
+
+    if args.disable_jpeg_reconstruction {
+        cmd.arg("--allow_expert_options")
+            .arg("--allow_jpeg_reconstruction")
+            .arg("0");
+    }
+
+    cmd
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write as _;
+
+    use super::*;
+
+    fn state_with_file(len: usize) -> (ConversionState, tempfile::NamedTempFile) {
+        let mut file = tempfile::NamedTempFile::new().unwrap();
+        file.write_all(&vec![0; len]).unwrap();
+
+        let conv = ConversionState::new(1);
+        let metadata = file.as_file().metadata().unwrap();
+
+        conv.push(FileEntry::new(
+            file.path().to_owned(),
+            FileType::PNG,
+            Some(FileType::PNG),
+            metadata,
+        ));
+
+        (conv, file)
+    }
+
+    #[test]
+    fn skipped_files_dont_count_against_budget() {
+        let dir = tempfile::tempdir().unwrap();
+        let conv = ConversionState::new(1);
+
+        for name in ["a.png", "b.png", "c.png"] {
+            let path = dir.path().join(name);
+            std::fs::write(&path, [0; 100]).unwrap();
+            std::fs::write(path.with_extension("png.jxl"), b"").unwrap();
+
+            let metadata = std::fs::metadata(&path).unwrap();
+            conv.push(FileEntry::new(path, FileType::PNG, Some(FileType::PNG), metadata));
+        }
+
+        conv.scan_complete.store(true, Ordering::Release);
+
+        let args: Conv2JxlArgs = argh::FromArgs::from_args(&["conv2jxl"], &["--byte-budget", "150"]).unwrap();
+        let mut stop = false;
+
+        for _ in 0..3 {
+            conv.next_file(0, &args, Instant::now(), &mut stop);
+        }
+
+        assert!(!stop);
+        assert!(conv.stop_reason.get().is_none());
+        assert_eq!(conv.claimed_bytes.load(Ordering::Relaxed), 0);
+        assert!((0..3).all(|i| matches!(conv.files[i].state.get(), Some(ConversionOutcome::Skipped))));
+    }
+
+    #[test]
+    fn warnings_are_combined() {
+        let mut warning = Some(Cow::Borrowed("Used lower quality due to inefficiency"));
+
+        add_warning(&mut warning, "Failed to link output for 'a.png'");
+        add_warning(&mut warning, format!("Failed to link output for '{}'", "b.png"));
+
+        assert_eq!(
+            warning.as_deref(),
+            Some(
+                "Used lower quality due to inefficiency. Failed to link output for 'a.png'. \
+                 Failed to link output for 'b.png'"
+            )
+        );
+
+        let mut warning = None;
+        add_warning(&mut warning, "Failed to set file times");
+
+        assert_eq!(warning.as_deref(), Some("Failed to set file times"));
+    }
+
+    #[test]
+    fn inefficient_without_retry_is_recorded() {
+        let (conv, _file) = state_with_file(100);
+
+        let attempt = Attempt {
+            quality: 90,
+            inefficient: true,
+            sizes: Some((100, 120)),
+            ..Attempt::default()
+        };
+
+        conv.settle_inefficient(0, &conv.files[0], &attempt, Instant::now());
+
+        assert!(matches!(
+            conv.files[0].state.get(),
+            Some(ConversionOutcome::Inefficient(100, 120))
+        ));
+
+        let progress = conv.progress.get(FileType::PNG);
+
+        assert_eq!(progress.inefficient.load(Ordering::Relaxed), 1);
+        assert_eq!(progress.total_bytes.load(Ordering::Relaxed), 0);
+        assert_eq!(conv.non_success.read().unwrap().len(), 1);
+    }
+
+    #[test]
+    fn settled_outcome_is_kept() {
+        let (conv, _file) = state_with_file(100);
+
+        conv.files[0].set_state(Instant::now(), ConversionOutcome::Success(100, 50));
+
+        let attempt = Attempt {
+            inefficient: true,
+            sizes: Some((100, 120)),
+            ..Attempt::default()
+        };
+
+        conv.settle_inefficient(0, &conv.files[0], &attempt, Instant::now());
+
+        assert!(matches!(
+            conv.files[0].state.get(),
+            Some(ConversionOutcome::Success(100, 50))
+        ));
+        assert_eq!(conv.progress.get(FileType::PNG).inefficient.load(Ordering::Relaxed), 0);
+    }
 }
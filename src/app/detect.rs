@@ -0,0 +1,170 @@
+use std::{
+    fs::File,
+    io::{Read, Seek, SeekFrom},
+    path::Path,
+};
+
+use crate::cli::FileType;
+
+/// Number of bytes read from the start of a file for sniffing.
+///
+/// This is enough to cover every fixed magic number, and usually the first few PNG chunks,
+/// which is where an `acTL` chunk would be found for animated PNGs.
+const SNIFF_LEN: u64 = 512;
+
+/// Detects the type of the file at `path` from its content, returning `None` if it isn't recognized.
+pub fn sniff(path: &Path) -> std::io::Result<Option<FileType>> {
+    let mut file = File::open(path)?;
+
+    let mut header = Vec::with_capacity(SNIFF_LEN as usize);
+    (&mut file).take(SNIFF_LEN).read_to_end(&mut header)?;
+
+    if let Some(ftype) = sniff_bytes(&header) {
+        return Ok(Some(ftype));
+    }
+
+    // TGA has no magic number in its header, only an optional footer in version 2.0 files
+    const TGA_FOOTER: &[u8; 18] = b"TRUEVISION-XFILE.\0";
+
+    let mut footer = [0u8; 18];
+
+    if file.seek(SeekFrom::End(-(footer.len() as i64))).is_ok()
+        && file.read_exact(&mut footer).is_ok()
+        && &footer == TGA_FOOTER
+    {
+        return Ok(Some(FileType::TGA));
+    }
+
+    Ok(None)
+}
+
+/// Detects the file type from the first bytes of a file.
+pub fn sniff_bytes(header: &[u8]) -> Option<FileType> {
+    const JXL_CONTAINER: &[u8] = b"\x00\x00\x00\x0cJXL \x0d\x0a\x87\x0a";
+    const PNG: &[u8] = b"\x89PNG\x0d\x0a\x1a\x0a";
+
+    Some(match header {
+        [0xFF, 0x0A, ..] => FileType::JXL,
+        _ if header.starts_with(JXL_CONTAINER) => FileType::JXL,
+        _ if header.starts_with(PNG) => sniff_png(&header[PNG.len()..]),
+        [0xFF, 0xD8, 0xFF, ..] => FileType::JPEG,
+        [b'G', b'I', b'F', b'8', b'7' | b'9', b'a', ..] => FileType::GIF,
+        [b'I', b'I', b'*' | b'+', 0, ..] | [b'M', b'M', 0, b'*' | b'+', ..] => FileType::TIFF,
+        [b'q', b'o', b'i', b'f', ..] => FileType::QOI,
+        // "BM" alone is too common a start, so the size of the info header after it must be a known one
+        [b'B', b'M', _, _, _, _, _, _, _, _, _, _, _, _, h0, h1, h2, h3, ..]
+            if matches!(
+                u32::from_le_bytes([*h0, *h1, *h2, *h3]),
+                12 | 16 | 40 | 52 | 56 | 64 | 108 | 124
+            ) =>
+        {
+            FileType::BMP
+        }
+        [b'P', b'K', 3, 4, ..] => FileType::ZIP,
+        [b'P', b'G', b' ', b'M', b'L', ..] | [b'P', b'G', b' ', b'L', b'M', ..] => FileType::PGX,
+        [b'P', b'3' | b'6', ws, ..] if ws.is_ascii_whitespace() => FileType::PPM,
+        [b'P', b'1' | b'2' | b'4' | b'5', ws, ..] if ws.is_ascii_whitespace() => FileType::PNM,
+        [b'P', b'7', ws, ..] if ws.is_ascii_whitespace() => FileType::PAM,
+        [b'P', b'F' | b'f', ws, ..] if ws.is_ascii_whitespace() => FileType::PFM,
+        _ => return None,
+    })
+}
+
+/// Walks the PNG chunks available in the header, looking for an `acTL` chunk before the first `IDAT`.
+fn sniff_png(mut chunks: &[u8]) -> FileType {
+    while let [l0, l1, l2, l3, t0, t1, t2, t3, ..] = *chunks {
+        match &[t0, t1, t2, t3] {
+            b"acTL" => return FileType::APNG,
+            b"IDAT" => break,
+            _ => {}
+        }
+
+        // chunk length + type + data + crc
+        let len = u32::from_be_bytes([l0, l1, l2, l3]) as usize;
+
+        let Some(rest) = chunks.get(len.saturating_add(12)..) else {
+            break;
+        };
+
+        chunks = rest;
+    }
+
+    FileType::PNG
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A PNG signature followed by chunks with the given types and empty data.
+    fn png(chunks: &[&[u8; 4]]) -> Vec<u8> {
+        let mut data = b"\x89PNG\x0d\x0a\x1a\x0a".to_vec();
+
+        for chunk in chunks {
+            data.extend_from_slice(&0u32.to_be_bytes());
+            data.extend_from_slice(*chunk);
+            data.extend_from_slice(&[0; 4]);
+        }
+
+        data
+    }
+
+    fn bmp(info_size: u32) -> Vec<u8> {
+        let mut data = b"BM".to_vec();
+        data.extend_from_slice(&[0; 12]);
+        data.extend_from_slice(&info_size.to_le_bytes());
+        data
+    }
+
+    #[test]
+    fn magic_numbers() {
+        assert_eq!(sniff_bytes(b"\xff\x0a..."), Some(FileType::JXL));
+        assert_eq!(
+            sniff_bytes(b"\x00\x00\x00\x0cJXL \x0d\x0a\x87\x0a"),
+            Some(FileType::JXL)
+        );
+        assert_eq!(sniff_bytes(b"\xff\xd8\xff\xe0"), Some(FileType::JPEG));
+        assert_eq!(sniff_bytes(b"GIF89a"), Some(FileType::GIF));
+        assert_eq!(sniff_bytes(b"GIF87a"), Some(FileType::GIF));
+        assert_eq!(sniff_bytes(b"II*\0"), Some(FileType::TIFF));
+        assert_eq!(sniff_bytes(b"MM\0+"), Some(FileType::TIFF));
+        assert_eq!(sniff_bytes(b"qoif"), Some(FileType::QOI));
+        assert_eq!(sniff_bytes(b"PK\x03\x04"), Some(FileType::ZIP));
+        assert_eq!(sniff_bytes(b"PG ML +8 1 1\n"), Some(FileType::PGX));
+        assert_eq!(sniff_bytes(b"P6\n1 1\n255\n"), Some(FileType::PPM));
+        assert_eq!(sniff_bytes(b"P5 1 1 255 "), Some(FileType::PNM));
+        assert_eq!(sniff_bytes(b"P7\nWIDTH 1\n"), Some(FileType::PAM));
+        assert_eq!(sniff_bytes(b"Pf\n1 1\n-1\n"), Some(FileType::PFM));
+    }
+
+    #[test]
+    fn unrecognized() {
+        assert_eq!(sniff_bytes(b""), None);
+        assert_eq!(sniff_bytes(b"\xff"), None);
+        assert_eq!(sniff_bytes(b"GIF90a"), None);
+        assert_eq!(sniff_bytes(b"P6x"), None);
+        assert_eq!(sniff_bytes(b"<?xml version"), None);
+    }
+
+    #[test]
+    fn animated_png() {
+        assert_eq!(sniff_bytes(&png(&[b"IHDR", b"acTL", b"IDAT"])), Some(FileType::APNG));
+        assert_eq!(sniff_bytes(&png(&[b"IHDR", b"IDAT", b"acTL"])), Some(FileType::PNG));
+        assert_eq!(sniff_bytes(&png(&[b"IHDR"])), Some(FileType::PNG));
+
+        // a chunk claiming more data than there is ends the walk
+        let mut truncated = png(&[b"IHDR"]);
+        truncated.extend_from_slice(&u32::MAX.to_be_bytes());
+        truncated.extend_from_slice(b"tEXt");
+        assert_eq!(sniff_bytes(&truncated), Some(FileType::PNG));
+    }
+
+    #[test]
+    fn bmp_needs_a_known_info_header() {
+        assert_eq!(sniff_bytes(&bmp(40)), Some(FileType::BMP));
+        assert_eq!(sniff_bytes(&bmp(124)), Some(FileType::BMP));
+        assert_eq!(sniff_bytes(&bmp(41)), None);
+        assert_eq!(sniff_bytes(b"BMP is not a bitmap"), None);
+        assert_eq!(sniff_bytes(b"BM"), None);
+    }
+}
@@ -0,0 +1,251 @@
+use std::{
+    fs::File,
+    io::Write,
+    path::Path,
+    sync::Mutex,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use serde::Serialize;
+
+use super::{FileEntry, convert::Attempt, journal::JournalOutcome};
+use crate::cli::Conv2JxlArgs;
+
+/// Encoder options of the attempt that decided the outcome.
+#[derive(Debug, Serialize)]
+struct Settings {
+    quality: u8,
+    effort: u8,
+    lossless_jpeg: bool,
+    jpeg_reconstruction: bool,
+    threads: i32,
+}
+
+/// A line of the error log.
+#[derive(Debug, Serialize)]
+struct Entry<'a> {
+    /// When the outcome was decided, in milliseconds since the Unix epoch
+    timestamp: u64,
+    path: &'a str,
+    #[serde(rename = "type")]
+    ftype: String,
+    outcome: JournalOutcome,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    message: Option<&'a str>,
+    settings: Settings,
+    /// Exit code of `cjxl`, if it ran and exited normally
+    #[serde(skip_serializing_if = "Option::is_none")]
+    exit_code: Option<i32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    stderr: Option<&'a str>,
+    input: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    output: Option<u64>,
+}
+
+/// Append-only log of the outcome of each file, as JSON Lines.
+pub struct ErrorLog {
+    file: Mutex<File>,
+    /// Log every outcome, not just failures, inefficient files and warnings
+    all: bool,
+}
+
+impl ErrorLog {
+    /// Opens the error log at `path` for appending, creating it if needed.
+    pub fn open(path: &Path, all: bool) -> std::io::Result<ErrorLog> {
+        let file = std::fs::OpenOptions::new().append(true).create(true).open(path)?;
+
+        Ok(ErrorLog {
+            file: Mutex::new(file),
+            all,
+        })
+    }
+
+    /// Appends the outcome of `file` if it is logged. A `message` on a converted file is a warning.
+    pub fn log(
+        &self,
+        file: &FileEntry,
+        args: &Conv2JxlArgs,
+        outcome: JournalOutcome,
+        sizes: Option<(u64, u64)>,
+        message: Option<&str>,
+        attempt: &Attempt,
+    ) -> std::io::Result<()> {
+        let issue = match outcome {
+            JournalOutcome::Converted => message.is_some(),
+            JournalOutcome::Started | JournalOutcome::Skipped => false,
+            JournalOutcome::Inefficient | JournalOutcome::Failed => true,
+        };
+
+        if !issue && !self.all {
+            return Ok(());
+        }
+
+        let path = file.path.to_string_lossy();
+        let stderr = attempt
+            .stderr
+            .as_deref()
+            .map(str::trim)
+            .filter(|stderr| !stderr.is_empty());
+
+        let entry = Entry {
+            timestamp: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map_or(0, |time| time.as_millis() as u64),
+            path: &path,
+            ftype: file.ext.to_string(),
+            outcome,
+            message,
+            settings: Settings {
+                quality: attempt.quality,
+                effort: args.effort,
+                lossless_jpeg: args.lossless_jpeg,
+                jpeg_reconstruction: !args.disable_jpeg_reconstruction,
+                threads: args.threads,
+            },
+            exit_code: attempt.status.and_then(|status| status.code()),
+            stderr,
+            input: sizes.map_or(file.metadata.len(), |(input, _)| input),
+            output: sizes.map(|(_, output)| output),
+        };
+
+        let mut line = serde_json::to_string(&entry).map_err(std::io::Error::other)?;
+        line.push('\n');
+
+        // a single write per entry, so entries from different threads never interleave
+        self.file.lock().unwrap().write_all(line.as_bytes())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::process::ExitStatus;
+
+    use serde_json::Value;
+
+    use super::*;
+    use crate::cli::FileType;
+
+    fn exit_status(code: i32) -> ExitStatus {
+        #[cfg(unix)]
+        return std::os::unix::process::ExitStatusExt::from_raw(code << 8);
+
+        #[cfg(windows)]
+        return std::os::windows::process::ExitStatusExt::from_raw(code as u32);
+    }
+
+    /// Logs every kind of outcome for a 100 byte file, returning the lines written.
+    fn logged(all: bool) -> Vec<Value> {
+        let dir = tempfile::tempdir().unwrap();
+        let (image, path) = (dir.path().join("a.png"), dir.path().join("errors.log"));
+
+        std::fs::write(&image, [0; 100]).unwrap();
+
+        let file = FileEntry::new(
+            image.clone(),
+            FileType::PNG,
+            Some(FileType::PNG),
+            std::fs::metadata(&image).unwrap(),
+        );
+        let args: Conv2JxlArgs = argh::FromArgs::from_args(&["conv2jxl"], &["-q", "90"]).unwrap();
+
+        let log = ErrorLog::open(&path, all).unwrap();
+
+        let attempt = Attempt {
+            quality: 90,
+            status: Some(exit_status(0)),
+            stderr: Some(String::new()),
+            ..Attempt::default()
+        };
+
+        let failed = Attempt {
+            status: Some(exit_status(1)),
+            stderr: Some("  unsupported input \n".to_owned()),
+            ..attempt
+        };
+
+        let converted = Some((100, 40));
+
+        log.log(&file, &args, JournalOutcome::Converted, converted, None, &attempt)
+            .unwrap();
+        log.log(&file, &args, JournalOutcome::Skipped, None, None, &Attempt::default())
+            .unwrap();
+        log.log(
+            &file,
+            &args,
+            JournalOutcome::Converted,
+            converted,
+            Some("lower quality"),
+            &attempt,
+        )
+        .unwrap();
+        log.log(
+            &file,
+            &args,
+            JournalOutcome::Inefficient,
+            Some((100, 120)),
+            None,
+            &attempt,
+        )
+        .unwrap();
+        log.log(
+            &file,
+            &args,
+            JournalOutcome::Failed,
+            None,
+            Some("conversion failed"),
+            &failed,
+        )
+        .unwrap();
+
+        std::fs::read_to_string(&path)
+            .unwrap()
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn only_issues_by_default() {
+        let lines = logged(false);
+
+        let outcomes = Vec::from_iter(lines.iter().map(|line| line["outcome"].as_str().unwrap()));
+        assert_eq!(outcomes, ["converted", "inefficient", "failed"]);
+
+        let [warning, inefficient, failed] = &lines[..] else {
+            unreachable!();
+        };
+
+        assert_eq!(warning["message"], "lower quality");
+        assert_eq!(
+            (&warning["input"], &warning["output"]),
+            (&Value::from(100), &Value::from(40))
+        );
+        assert_eq!(warning["exit_code"], 0);
+        // empty output from cjxl is left out
+        assert!(warning.get("stderr").is_none());
+        assert_eq!(warning["settings"]["quality"], 90);
+        assert!(warning["path"].as_str().unwrap().ends_with("a.png"));
+        assert_eq!(warning["type"], "png");
+
+        assert_eq!(inefficient["output"], 120);
+        assert!(inefficient.get("message").is_none());
+
+        assert_eq!(failed["exit_code"], 1);
+        assert_eq!(failed["stderr"], "unsupported input");
+        assert_eq!(failed["input"], 100);
+        assert!(failed.get("output").is_none());
+    }
+
+    #[test]
+    fn every_outcome_with_all() {
+        let lines = logged(true);
+
+        let outcomes = Vec::from_iter(lines.iter().map(|line| line["outcome"].as_str().unwrap()));
+        assert_eq!(outcomes, ["converted", "skipped", "converted", "inefficient", "failed"]);
+
+        assert!(lines[0].get("message").is_none());
+        // cjxl never ran for the skipped file
+        assert!(lines[1].get("exit_code").is_none() && lines[1].get("output").is_none());
+    }
+}
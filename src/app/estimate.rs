@@ -0,0 +1,354 @@
+use std::{collections::BTreeMap, fmt::Write as _};
+
+use rand::{SeedableRng, seq::index};
+use rand_chacha::ChaCha8Rng;
+
+use super::*;
+use crate::{
+    cli::EstimateArgs,
+    formatting::{Bytes, TimeBreakdown},
+};
+
+/// Files of one type in one size range, which are sampled separately
+/// so that a few large files can't skew the estimate for many small ones.
+#[derive(Default)]
+struct Stratum {
+    /// Indices of the files found
+    files: Vec<usize>,
+    bytes: u64,
+    /// Indices of the sampled copies
+    sampled: Vec<usize>,
+}
+
+/// Size range of a file, each 4 times larger than the one before.
+fn size_bucket(len: u64) -> u32 {
+    (u64::BITS - len.leading_zeros()).div_ceil(2)
+}
+
+/// An extrapolated total and the variance of the estimate.
+#[derive(Debug, Default, Clone, Copy)]
+struct Projection {
+    value: f64,
+    /// NaN if unknown, which carries over to any total it is added to
+    variance: f64,
+}
+
+impl Projection {
+    /// Half-width of the 95% confidence interval, or `None` if there weren't enough samples to tell.
+    fn margin(&self) -> Option<f64> {
+        (!self.variance.is_nan()).then(|| 1.96 * self.variance.sqrt())
+    }
+
+    fn add(&mut self, other: Projection) {
+        self.value += other.value;
+        self.variance += other.variance;
+    }
+
+    /// Projects the total of `values` from a sample onto a population of `population`,
+    /// with the variance corrected for sampling without replacement.
+    fn from_sample(values: &[f64], population: usize) -> Projection {
+        let n = values.len() as f64;
+        let population = population as f64;
+
+        let mean = values.iter().sum::<f64>() / n.max(1.0);
+
+        // a single sample has no spread to go by, unless it was the whole population
+        let sample_variance = match values.len() {
+            0 | 1 if n >= population => 0.0,
+            0 | 1 => f64::NAN,
+            _ => values.iter().map(|v| (v - mean).powi(2)).sum::<f64>() / (n - 1.0),
+        };
+
+        Projection {
+            value: mean * population,
+            variance: population * population * (1.0 - n / population) * sample_variance / n.max(1.0),
+        }
+    }
+}
+
+#[derive(Default)]
+struct TypeTotals {
+    files: usize,
+    bytes: u64,
+    savings: Projection,
+    /// Conversion time in milliseconds, as if converted one at a time
+    time: Projection,
+}
+
+/// Converts a stratified random sample of the files found into a temporary directory,
+/// and extrapolates the savings and conversion time for all of them.
+pub fn run(mut args: Conv2JxlArgs, estimate: &EstimateArgs) -> Result<(), Box<dyn std::error::Error>> {
+    let seed = *args.seed.get_or_insert_with(rand::random);
+
+    eprintln!("Scanning...");
+
+    let found = args.scan(&scan::ScanObserver::default())?;
+
+    let mut strata = PerFileType::<BTreeMap<u32, Stratum>>::default();
+
+    for i in 0..found.files.len() {
+        let file = &found.files[i];
+        let stratum = strata
+            .get_mut(file.ext)
+            .entry(size_bucket(file.metadata.len()))
+            .or_default();
+
+        stratum.files.push(i);
+        stratum.bytes += file.metadata.len();
+    }
+
+    // separate from the streams used for --sample and --randomize
+    let mut rng = ChaCha8Rng::seed_from_u64(seed);
+    rng.set_stream(2);
+
+    let tmp = tempfile::tempdir()?;
+    let samples = ConversionState::new(args.parallel as usize);
+
+    for &ftype in FileType::all() {
+        for stratum in strata.get_mut(ftype).values_mut() {
+            let amount = estimate.samples.min(stratum.files.len());
+
+            for k in index::sample(&mut rng, stratum.files.len(), amount) {
+                let file = &found.files[stratum.files[k]];
+
+                // each copy gets its own directory, so names can't clash and outputs land next to it
+                let dir = tmp.path().join(samples.files.len().to_string());
+                let copy = dir.join(file.path.file_name().unwrap_or_default());
+
+                if let Err(e) = std::fs::create_dir(&dir).and_then(|_| std::fs::copy(&file.path, &copy)) {
+                    eprintln!("Failed to copy '{}', leaving it out: {e}", file.path.display());
+                    continue;
+                }
+
+                stratum.sampled.push(samples.files.len());
+                samples.push(FileEntry::new(copy, file.ext, file.named, file.metadata.clone()).with_info(file.info));
+            }
+        }
+    }
+
+    samples.scan_complete.store(true, Ordering::Release);
+
+    eprintln!("Converting {} of {} files...", samples.files.len(), found.files.len());
+
+    // only the copies are converted, so whatever the options, nothing in the tree is touched
+    args.dry_run = false;
+    args.delete = false;
+    args.truncate = false;
+    args.overwrite = true;
+    args.time_budget = None;
+    args.byte_budget = None;
+
+    let shared = SharedState {
+        args,
+        conv: samples,
+        start: Instant::now(),
+    };
+
+    // microseconds taken to convert each sample
+    let durations = Vec::from_iter((0..shared.conv.files.len()).map(|_| AtomicU64::new(0)));
+
+    std::thread::scope(|s| {
+        for thread_idx in 0..shared.args.parallel as usize {
+            let (shared, durations) = (&shared, &durations);
+
+            s.spawn(move || {
+                let mut stop = false;
+
+                loop {
+                    let start = Instant::now();
+
+                    shared.next(thread_idx, &mut stop);
+
+                    if stop {
+                        break;
+                    }
+
+                    let i = shared.conv.active[thread_idx].file_idx.load(Ordering::Relaxed);
+
+                    durations[i].store(start.elapsed().as_micros() as u64, Ordering::Relaxed);
+                }
+            });
+        }
+    });
+
+    let conv = &shared.conv;
+    let mut totals = PerFileType::<TypeTotals>::default();
+
+    for &ftype in FileType::all() {
+        let type_totals = totals.get_mut(ftype);
+
+        for stratum in strata.get(ftype).values() {
+            type_totals.files += stratum.files.len();
+            type_totals.bytes += stratum.bytes;
+
+            if stratum.sampled.is_empty() {
+                continue;
+            }
+
+            // files that fail or don't get smaller enough are kept, saving nothing
+            let saved = Vec::from_iter(stratum.sampled.iter().map(|&i| match conv.files[i].state.get() {
+                Some(ConversionOutcome::Success(input, output) | ConversionOutcome::Warning(input, output, _)) => {
+                    input.saturating_sub(*output) as f64
+                }
+                _ => 0.0,
+            }));
+
+            let input = Vec::from_iter(stratum.sampled.iter().map(|&i| conv.files[i].metadata.len() as f64));
+            let sample_bytes = input.iter().sum::<f64>();
+            let ratio = saved.iter().sum::<f64>() / sample_bytes.max(1.0);
+
+            // savings scale with size even within a size range, so project the ratio of saved bytes,
+            // with the spread of what each file saved compared to that ratio
+            let residuals = Vec::from_iter(saved.iter().zip(&input).map(|(saved, input)| saved - ratio * input));
+
+            let savings = Projection {
+                value: ratio * stratum.bytes as f64,
+                variance: Projection::from_sample(&residuals, stratum.files.len()).variance,
+            };
+
+            let times = Vec::from_iter(
+                stratum
+                    .sampled
+                    .iter()
+                    .map(|&i| durations[i].load(Ordering::Relaxed) as f64 / 1000.0),
+            );
+
+            type_totals.savings.add(savings);
+            type_totals
+                .time
+                .add(Projection::from_sample(&times, stratum.files.len()));
+        }
+    }
+
+    print!("{}", report(&shared, &totals, seed));
+
+    Ok(())
+}
+
+fn report(shared: &SharedState, totals: &PerFileType<TypeTotals>, seed: u64) -> String {
+    let mut out = String::new();
+
+    let _ = writeln!(
+        out,
+        "{:<6} {:>9} {:>11} {:>7} {:>9} {:>6} {:>6} {:>28} {:>24}",
+        "type", "files", "size", "sampled", "converted", "kept", "failed", "estimated savings (95%)", "time (95%)"
+    );
+
+    let mut all = TypeTotals::default();
+    let mut sampled = 0;
+
+    for (ftype, type_totals) in totals.iter() {
+        if type_totals.files == 0 {
+            continue;
+        }
+
+        let progress = shared.conv.progress.get(ftype);
+        let total = progress.total.load(Ordering::Relaxed);
+        let errored = progress.errored.load(Ordering::Relaxed);
+        let inefficient = progress.inefficient.load(Ordering::Relaxed);
+
+        let _ = writeln!(
+            out,
+            "{:<6} {:>9} {:>11} {:>7} {:>9} {:>6} {:>6} {:>28} {:>24}",
+            ftype.to_string(),
+            type_totals.files,
+            Bytes(type_totals.bytes).to_string(),
+            total,
+            progress.processed.load(Ordering::Relaxed),
+            inefficient,
+            errored,
+            savings(type_totals),
+            time(type_totals.time),
+        );
+
+        all.files += type_totals.files;
+        all.bytes += type_totals.bytes;
+        all.savings.add(type_totals.savings);
+        all.time.add(type_totals.time);
+        sampled += total;
+    }
+
+    let _ = writeln!(
+        out,
+        "{:<6} {:>9} {:>11} {:>7} {:>9} {:>6} {:>6} {:>28} {:>24}",
+        "total",
+        all.files,
+        Bytes(all.bytes).to_string(),
+        sampled,
+        "",
+        "",
+        "",
+        savings(&all),
+        time(all.time),
+    );
+
+    let parallel = shared.args.parallel.max(1) as f64;
+
+    let _ = writeln!(
+        out,
+        "\nEstimated run time with --parallel {}: {} (± {}). Seed: {seed}",
+        shared.args.parallel,
+        TimeBreakdown(all.time.value / parallel),
+        margin(all.time, |margin| TimeBreakdown(margin / parallel).to_string()),
+    );
+
+    out
+}
+
+fn savings(totals: &TypeTotals) -> String {
+    let percent = totals.savings.value / (totals.bytes as f64).max(1.0) * 100.0;
+
+    format!(
+        "{} ± {} ({percent:.1}%)",
+        Bytes(totals.savings.value.max(0.0) as u64),
+        margin(totals.savings, |margin| Bytes(margin as u64).to_string()),
+    )
+}
+
+fn time(time: Projection) -> String {
+    format!(
+        "{} ± {}",
+        TimeBreakdown(time.value),
+        margin(time, |margin| TimeBreakdown(margin).to_string())
+    )
+}
+
+/// Formats the margin of a projection, or "?" if it is unknown.
+fn margin(projection: Projection, format: impl FnOnce(f64) -> String) -> String {
+    projection.margin().map_or_else(|| "?".to_owned(), format)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn projection() {
+        let p = Projection::from_sample(&[1.0, 2.0, 3.0], 30);
+
+        assert_eq!(p.value, 60.0);
+        // 30² * (1 - 3/30) * 1 / 3
+        assert!((p.variance - 270.0).abs() < 1e-9);
+        assert!(p.margin().is_some_and(|m| m > 0.0));
+    }
+
+    #[test]
+    fn single_sample_is_unknown() {
+        let p = Projection::from_sample(&[5.0], 10);
+
+        assert_eq!(p.value, 50.0);
+        assert_eq!(p.margin(), None);
+
+        // and so is any total it is part of
+        let mut total = Projection::from_sample(&[1.0, 3.0], 4);
+        total.add(p);
+        assert_eq!(total.margin(), None);
+        assert_eq!(margin(total, |m| m.to_string()), "?");
+    }
+
+    #[test]
+    fn whole_population_is_exact() {
+        assert_eq!(Projection::from_sample(&[5.0], 1).margin(), Some(0.0));
+        assert_eq!(Projection::from_sample(&[1.0, 3.0], 2).margin(), Some(0.0));
+    }
+}
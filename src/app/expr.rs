@@ -0,0 +1,616 @@
+//! A small expression language for filtering images by their properties, e.g.
+//! `width >= 1000 && !animated && (color == rgb || color == rgba)`.
+
+use std::{error::Error, fmt::Display, str::FromStr};
+
+use super::probe::{ColorKind, ImageInfo};
+use crate::cli::{duration_unit, size_unit};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Property {
+    Width,
+    Height,
+    Pixels,
+    Aspect,
+    BitDepth,
+    Channels,
+    HasAlpha,
+    Animated,
+    Color,
+    Size,
+    /// Time since last modification, in seconds
+    MTime,
+}
+
+impl Property {
+    const NAMES: [(&'static str, Property); 15] = [
+        ("width", Property::Width),
+        ("height", Property::Height),
+        ("pixels", Property::Pixels),
+        ("aspect", Property::Aspect),
+        ("aspect_ratio", Property::Aspect),
+        ("bit_depth", Property::BitDepth),
+        ("depth", Property::BitDepth),
+        ("channels", Property::Channels),
+        ("has_alpha", Property::HasAlpha),
+        ("alpha", Property::HasAlpha),
+        ("animated", Property::Animated),
+        ("color", Property::Color),
+        ("size", Property::Size),
+        ("mtime", Property::MTime),
+        ("age", Property::MTime),
+    ];
+
+    /// Returns true if the property can only be known by reading the image header.
+    fn needs_header(self) -> bool {
+        !matches!(self, Property::Size | Property::MTime)
+    }
+}
+
+const COLORS: [(&str, ColorKind); 7] = [
+    ("gray", ColorKind::Gray),
+    ("grey", ColorKind::Gray),
+    ("gray_alpha", ColorKind::GrayAlpha),
+    ("rgb", ColorKind::Rgb),
+    ("rgba", ColorKind::Rgba),
+    ("palette", ColorKind::Palette),
+    ("cmyk", ColorKind::Cmyk),
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CmpOp {
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Expr {
+    Number(f64),
+    Bool(bool),
+    Color(ColorKind),
+    Property(Property),
+    Not(Box<Expr>),
+    And(Box<Expr>, Box<Expr>),
+    Or(Box<Expr>, Box<Expr>),
+    Cmp(Box<Expr>, CmpOp, Box<Expr>),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Value {
+    Number(f64),
+    Bool(bool),
+    Color(ColorKind),
+    /// The property isn't known for this image
+    Unknown,
+}
+
+/// Everything known about a file that an expression can refer to.
+pub struct Properties<'a> {
+    /// Only available if the expression needs it
+    pub info: Option<&'a ImageInfo>,
+    pub metadata: &'a std::fs::Metadata,
+}
+
+impl Properties<'_> {
+    fn get(&self, property: Property) -> Value {
+        let number = |n: Option<f64>| n.map_or(Value::Unknown, Value::Number);
+        let info = self.info;
+
+        match property {
+            Property::Width => number(info.map(|i| i.width as f64)),
+            Property::Height => number(info.map(|i| i.height as f64)),
+            Property::Pixels => number(info.map(|i| i.width as f64 * i.height as f64)),
+            Property::Aspect => number(info.filter(|i| i.height > 0).map(|i| i.width as f64 / i.height as f64)),
+            Property::BitDepth => number(info.and_then(|i| i.bit_depth).map(f64::from)),
+            Property::Channels => number(info.and_then(|i| i.channels).map(f64::from)),
+            Property::HasAlpha => info.and_then(|i| i.has_alpha).map_or(Value::Unknown, Value::Bool),
+            Property::Animated => info.and_then(|i| i.animated).map_or(Value::Unknown, Value::Bool),
+            Property::Color => info.and_then(|i| i.color).map_or(Value::Unknown, Value::Color),
+            Property::Size => Value::Number(self.metadata.len() as f64),
+            Property::MTime => number(
+                self.metadata
+                    .modified()
+                    .ok()
+                    .map(|t| t.elapsed().unwrap_or_default().as_secs_f64()),
+            ),
+        }
+    }
+}
+
+impl Expr {
+    /// Returns true if evaluating the expression requires reading the image header.
+    pub fn needs_header(&self) -> bool {
+        match self {
+            Expr::Number(_) | Expr::Bool(_) | Expr::Color(_) => false,
+            Expr::Property(p) => p.needs_header(),
+            Expr::Not(e) => e.needs_header(),
+            Expr::And(a, b) | Expr::Or(a, b) | Expr::Cmp(a, _, b) => a.needs_header() || b.needs_header(),
+        }
+    }
+
+    /// Evaluates the expression, where anything involving an unknown property is false.
+    pub fn matches(&self, props: &Properties) -> bool {
+        self.truth(props).unwrap_or(false)
+    }
+
+    /// Three-valued logic, with `None` for unknown, so that e.g. `!animated` isn't true just because
+    /// animation can't be told for a format.
+    fn truth(&self, props: &Properties) -> Option<bool> {
+        match self {
+            Expr::Not(e) => e.truth(props).map(|b| !b),
+            Expr::And(a, b) => match (a.truth(props), b.truth(props)) {
+                (Some(false), _) | (_, Some(false)) => Some(false),
+                (Some(true), Some(true)) => Some(true),
+                _ => None,
+            },
+            Expr::Or(a, b) => match (a.truth(props), b.truth(props)) {
+                (Some(true), _) | (_, Some(true)) => Some(true),
+                (Some(false), Some(false)) => Some(false),
+                _ => None,
+            },
+            Expr::Cmp(a, op, b) => {
+                let ordering = match (a.value(props), b.value(props)) {
+                    (Value::Number(a), Value::Number(b)) => a.partial_cmp(&b)?,
+                    (Value::Bool(a), Value::Bool(b)) => a.cmp(&b),
+                    (Value::Color(a), Value::Color(b)) => match a == b {
+                        true => std::cmp::Ordering::Equal,
+                        // colors are unordered, so only equality makes sense
+                        false if matches!(op, CmpOp::Eq | CmpOp::Ne) => std::cmp::Ordering::Less,
+                        false => return Some(false),
+                    },
+                    _ => return None,
+                };
+
+                Some(match op {
+                    CmpOp::Eq => ordering.is_eq(),
+                    CmpOp::Ne => ordering.is_ne(),
+                    CmpOp::Lt => ordering.is_lt(),
+                    CmpOp::Le => ordering.is_le(),
+                    CmpOp::Gt => ordering.is_gt(),
+                    CmpOp::Ge => ordering.is_ge(),
+                })
+            }
+            _ => match self.value(props) {
+                Value::Bool(b) => Some(b),
+                // a bare number is true if nonzero
+                Value::Number(n) => Some(n != 0.0),
+                Value::Color(_) => Some(true),
+                Value::Unknown => None,
+            },
+        }
+    }
+
+    fn value(&self, props: &Properties) -> Value {
+        match *self {
+            Expr::Number(n) => Value::Number(n),
+            Expr::Bool(b) => Value::Bool(b),
+            Expr::Color(c) => Value::Color(c),
+            Expr::Property(p) => props.get(p),
+            _ => self.truth(props).map_or(Value::Unknown, Value::Bool),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct InvalidExpression(pub String);
+
+impl Display for InvalidExpression {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "invalid expression: {}", self.0)
+    }
+}
+
+impl Error for InvalidExpression {}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Number(f64),
+    Ident(String),
+    Str(String),
+    Cmp(CmpOp),
+    And,
+    Or,
+    Not,
+    Open,
+    Close,
+}
+
+fn tokenize(s: &str) -> Result<Vec<Token>, InvalidExpression> {
+    let mut tokens = Vec::new();
+    let mut chars = s.char_indices().peekable();
+
+    let err = |msg: String| Err(InvalidExpression(msg));
+
+    while let Some(&(start, c)) = chars.peek() {
+        if c.is_whitespace() {
+            chars.next();
+            continue;
+        }
+
+        // takes characters while the predicate holds, returning the slice
+        let mut take_while = |pred: fn(char) -> bool| {
+            let mut end = start;
+
+            while let Some(&(i, c)) = chars.peek() {
+                if !pred(c) {
+                    break;
+                }
+
+                end = i + c.len_utf8();
+                chars.next();
+            }
+
+            &s[start..end]
+        };
+
+        if c.is_ascii_digit() || c == '.' {
+            let number = take_while(|c| c.is_ascii_digit() || c == '.' || c == 'e' || c == 'E');
+            let Ok(value) = number.parse::<f64>() else {
+                return err(format!("invalid number '{number}'"));
+            };
+
+            let unit_start = start + number.len();
+            let unit = {
+                let mut end = unit_start;
+
+                while let Some(&(i, c)) = chars.peek() {
+                    if !c.is_ascii_alphabetic() {
+                        break;
+                    }
+
+                    end = i + 1;
+                    chars.next();
+                }
+
+                &s[unit_start..end]
+            };
+
+            // sizes and durations share the same tables as the options taking them,
+            // where "m" means megabytes for one and minutes for the other
+            let multiplier = match unit {
+                "" => 1.0,
+                _ if unit.eq_ignore_ascii_case("m") => {
+                    return err(format!(
+                        "ambiguous unit '{unit}', use 'min' for minutes, or 'MB' or 'e6' otherwise"
+                    ));
+                }
+                _ => match size_unit(unit).or_else(|| duration_unit(unit)) {
+                    Some(m) => m,
+                    None => return err(format!("unknown unit '{unit}'")),
+                },
+            };
+
+            tokens.push(Token::Number(value * multiplier));
+        } else if c.is_alphabetic() || c == '_' {
+            let ident = take_while(|c| c.is_alphanumeric() || c == '_');
+
+            tokens.push(match ident.to_ascii_lowercase().as_str() {
+                "and" => Token::And,
+                "or" => Token::Or,
+                "not" => Token::Not,
+                _ => Token::Ident(ident.to_owned()),
+            });
+        } else if c == '"' || c == '\'' {
+            chars.next();
+
+            let mut value = String::new();
+
+            loop {
+                match chars.next() {
+                    Some((_, q)) if q == c => break,
+                    Some((_, ch)) => value.push(ch),
+                    None => return err("unterminated string".to_owned()),
+                }
+            }
+
+            tokens.push(Token::Str(value));
+        } else {
+            chars.next();
+
+            let next = chars.peek().map(|&(_, c)| c);
+
+            let mut two = |token: Token| {
+                chars.next();
+                token
+            };
+
+            tokens.push(match (c, next) {
+                ('&', Some('&')) => two(Token::And),
+                ('|', Some('|')) => two(Token::Or),
+                ('=', Some('=')) => two(Token::Cmp(CmpOp::Eq)),
+                ('!', Some('=')) => two(Token::Cmp(CmpOp::Ne)),
+                ('<', Some('=')) => two(Token::Cmp(CmpOp::Le)),
+                ('>', Some('=')) => two(Token::Cmp(CmpOp::Ge)),
+                ('=', _) => Token::Cmp(CmpOp::Eq),
+                ('<', _) => Token::Cmp(CmpOp::Lt),
+                ('>', _) => Token::Cmp(CmpOp::Gt),
+                ('!', _) => Token::Not,
+                ('(', _) => Token::Open,
+                (')', _) => Token::Close,
+                _ => return err(format!("unexpected character '{c}'")),
+            });
+        }
+    }
+
+    Ok(tokens)
+}
+
+/// Recursive descent parser, with precedence from lowest to highest: or, and, not, comparison.
+struct Parser {
+    tokens: Vec<Token>,
+    pos: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos)
+    }
+
+    fn eat(&mut self, token: &Token) -> bool {
+        let found = self.peek() == Some(token);
+
+        if found {
+            self.pos += 1;
+        }
+
+        found
+    }
+
+    fn or(&mut self) -> Result<Expr, InvalidExpression> {
+        let mut expr = self.and()?;
+
+        while self.eat(&Token::Or) {
+            expr = Expr::Or(Box::new(expr), Box::new(self.and()?));
+        }
+
+        Ok(expr)
+    }
+
+    fn and(&mut self) -> Result<Expr, InvalidExpression> {
+        let mut expr = self.not()?;
+
+        while self.eat(&Token::And) {
+            expr = Expr::And(Box::new(expr), Box::new(self.not()?));
+        }
+
+        Ok(expr)
+    }
+
+    fn not(&mut self) -> Result<Expr, InvalidExpression> {
+        if self.eat(&Token::Not) {
+            return Ok(Expr::Not(Box::new(self.not()?)));
+        }
+
+        self.cmp()
+    }
+
+    fn cmp(&mut self) -> Result<Expr, InvalidExpression> {
+        let lhs = self.atom()?;
+
+        if let Some(&Token::Cmp(op)) = self.peek() {
+            self.pos += 1;
+
+            return Ok(Expr::Cmp(Box::new(lhs), op, Box::new(self.atom()?)));
+        }
+
+        Ok(lhs)
+    }
+
+    fn atom(&mut self) -> Result<Expr, InvalidExpression> {
+        let Some(token) = self.tokens.get(self.pos).cloned() else {
+            return Err(InvalidExpression("unexpected end of expression".to_owned()));
+        };
+
+        self.pos += 1;
+
+        match token {
+            Token::Number(n) => Ok(Expr::Number(n)),
+            Token::Open => {
+                let expr = self.or()?;
+
+                match self.eat(&Token::Close) {
+                    true => Ok(expr),
+                    false => Err(InvalidExpression("missing ')'".to_owned())),
+                }
+            }
+            Token::Ident(name) | Token::Str(name) => {
+                let name = name.to_ascii_lowercase();
+
+                match name.as_str() {
+                    "true" => return Ok(Expr::Bool(true)),
+                    "false" => return Ok(Expr::Bool(false)),
+                    _ => {}
+                }
+
+                if let Some(&(_, p)) = Property::NAMES.iter().find(|(n, _)| *n == name) {
+                    return Ok(Expr::Property(p));
+                }
+
+                if let Some(&(_, c)) = COLORS.iter().find(|(n, _)| *n == name) {
+                    return Ok(Expr::Color(c));
+                }
+
+                Err(InvalidExpression(format!("unknown property or value '{name}'")))
+            }
+            other => Err(InvalidExpression(format!("unexpected {other:?}"))),
+        }
+    }
+}
+
+impl FromStr for Expr {
+    type Err = InvalidExpression;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut parser = Parser {
+            tokens: tokenize(s)?,
+            pos: 0,
+        };
+
+        let expr = parser.or()?;
+
+        match parser.peek() {
+            None => Ok(expr),
+            Some(token) => Err(InvalidExpression(format!("unexpected {token:?}"))),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+
+    use super::*;
+
+    fn parse(s: &str) -> Expr {
+        s.parse().unwrap()
+    }
+
+    fn number(s: &str) -> f64 {
+        match parse(s) {
+            Expr::Number(n) => n,
+            other => panic!("expected a number, got {other:?}"),
+        }
+    }
+
+    /// Evaluates the expression against a file of `size` bytes with the given header.
+    fn eval(s: &str, info: Option<&ImageInfo>, size: usize) -> bool {
+        let mut file = tempfile::tempfile().unwrap();
+        file.write_all(&vec![0; size]).unwrap();
+
+        let metadata = file.metadata().unwrap();
+
+        parse(s).matches(&Properties {
+            info,
+            metadata: &metadata,
+        })
+    }
+
+    fn rgb(width: u32, height: u32) -> ImageInfo {
+        ImageInfo {
+            width,
+            height,
+            bit_depth: Some(8),
+            channels: Some(3),
+            has_alpha: Some(false),
+            animated: Some(false),
+            color: Some(ColorKind::Rgb),
+        }
+    }
+
+    #[test]
+    fn precedence() {
+        let (a, b, c) = (
+            || Box::new(Expr::Property(Property::Width)),
+            || Box::new(Expr::Property(Property::Height)),
+            || Box::new(Expr::Property(Property::Animated)),
+        );
+
+        // and binds tighter than or, and not tighter than both
+        assert_eq!(
+            parse("width || height && animated"),
+            Expr::Or(a(), Box::new(Expr::And(b(), c())))
+        );
+        assert_eq!(
+            parse("(width || height) && animated"),
+            Expr::And(Box::new(Expr::Or(a(), b())), c())
+        );
+        assert_eq!(parse("!width && height"), Expr::And(Box::new(Expr::Not(a())), b()));
+        assert_eq!(parse("not width or height"), Expr::Or(Box::new(Expr::Not(a())), b()));
+
+        // comparisons bind tightest
+        assert_eq!(
+            parse("!width > 10"),
+            Expr::Not(Box::new(Expr::Cmp(a(), CmpOp::Gt, Box::new(Expr::Number(10.0)))))
+        );
+    }
+
+    #[test]
+    fn units() {
+        assert_eq!(number("10"), 10.0);
+        assert_eq!(number("1.5e3"), 1500.0);
+        assert_eq!(number("10MB"), 10e6);
+        assert_eq!(number("2MiB"), 2.0 * 1024.0 * 1024.0);
+        assert_eq!(number("2kib"), 2048.0);
+        assert_eq!(number("1.5K"), 1500.0);
+        assert_eq!(number("3G"), 3e9);
+        assert_eq!(number("500ms"), 0.5);
+        assert_eq!(number("10min"), 600.0);
+        assert_eq!(number("2h"), 7200.0);
+        assert_eq!(number("30d"), 30.0 * 86400.0);
+        assert_eq!(number("1w"), 604800.0);
+    }
+
+    #[test]
+    fn errors() {
+        for s in [
+            "",
+            "width >",
+            "(width > 10",
+            "width > 10)",
+            "width >> 10",
+            "10m",
+            "10M",
+            "10xyz",
+            "1.2.3",
+            "depth == 'rgb",
+            "widht > 10",
+            "width $ 10",
+            "width 10",
+        ] {
+            assert!(s.parse::<Expr>().is_err(), "{s:?} should not parse");
+        }
+    }
+
+    #[test]
+    fn comparisons() {
+        let info = rgb(1920, 1080);
+
+        assert!(eval("width == 1920 && height < 1080.5", Some(&info), 0));
+        assert!(eval("pixels >= 2e6 && aspect > 1.7", Some(&info), 0));
+        assert!(eval("color == rgb && color != rgba && !animated", Some(&info), 0));
+        assert!(eval("color == 'RGB'", Some(&info), 0));
+        assert!(eval("size >= 1KiB && size < 1.1KB", Some(&info), 1024));
+        assert!(eval("mtime < 1min", Some(&info), 0));
+        assert!(!eval("mtime > 1h", Some(&info), 0));
+
+        // colors are unordered
+        assert!(!eval("color < rgba", Some(&info), 0));
+        assert!(!eval("color > rgba", Some(&info), 0));
+    }
+
+    #[test]
+    fn missing_properties() {
+        let info = ImageInfo {
+            width: 100,
+            height: 0,
+            ..ImageInfo::default()
+        };
+
+        // neither a comparison nor its negation holds for an unknown property
+        assert!(!eval("animated", Some(&info), 0));
+        assert!(!eval("!animated", Some(&info), 0));
+        assert!(!eval("bit_depth > 8", Some(&info), 0));
+        assert!(!eval("!(bit_depth > 8)", Some(&info), 0));
+        assert!(!eval("aspect > 0", Some(&info), 0));
+
+        // unless the other side decides
+        assert!(eval("animated || width == 100", Some(&info), 0));
+        assert!(!eval("animated && width == 100", Some(&info), 0));
+        assert!(eval("!(animated && width == 1)", Some(&info), 0));
+
+        // without a header, only size and mtime are known
+        assert!(!eval("width > 0", None, 10));
+        assert!(!eval("!(width > 0)", None, 10));
+        assert!(eval("size == 10", None, 10));
+    }
+
+    #[test]
+    fn needs_header() {
+        assert!(!parse("size > 1MB || mtime > 1d").needs_header());
+        assert!(parse("size > 1MB || !animated").needs_header());
+    }
+}
@@ -0,0 +1,147 @@
+use std::path::Path;
+
+use globset::{Candidate, GlobSet, GlobSetBuilder};
+use regex::bytes::{RegexSet, RegexSetBuilder};
+
+/// Include and exclude patterns on full paths, matched against the raw path bytes
+/// so that paths which aren't valid UTF-8 are filtered like any other.
+#[derive(Debug, Default)]
+pub struct PathFilter {
+    include: Option<Patterns>,
+    exclude: Option<Patterns>,
+}
+
+/// Globs and regexes, matching a path if any of them does.
+#[derive(Debug)]
+struct Patterns {
+    /// Matched with the platform's path separators, so that `/` in a glob also matches `\` on Windows
+    globs: GlobSet,
+    regexes: RegexSet,
+}
+
+impl Patterns {
+    /// Builds the patterns, returning `None` if there are none. Patterns prefixed with `glob:` are globs,
+    /// anything else is a regex, optionally prefixed with `re:` or `regex:`.
+    fn new<'a>(patterns: impl IntoIterator<Item = &'a String>) -> Result<Option<Self>, Box<dyn std::error::Error>> {
+        let (mut globs, mut regexes) = (GlobSetBuilder::new(), Vec::new());
+        let mut empty = true;
+
+        for pattern in patterns {
+            empty = false;
+
+            if let Some(glob) = pattern.strip_prefix("glob:") {
+                globs.add(globset::Glob::new(glob)?);
+                continue;
+            }
+
+            let regex = pattern
+                .strip_prefix("re:")
+                .or_else(|| pattern.strip_prefix("regex:"))
+                .unwrap_or(pattern);
+
+            regexes.push(regex);
+        }
+
+        if empty {
+            return Ok(None);
+        }
+
+        Ok(Some(Patterns {
+            globs: globs.build()?,
+            // without Unicode, classes like `.` match any byte, not just whole UTF-8 characters
+            regexes: RegexSetBuilder::new(regexes).unicode(false).build()?,
+        }))
+    }
+
+    fn is_match(&self, path: &Path) -> bool {
+        self.regexes.is_match(path.as_os_str().as_encoded_bytes())
+            || (!self.globs.is_empty() && self.globs.is_match_candidate(&Candidate::new(path)))
+    }
+}
+
+impl PathFilter {
+    pub fn new<'a>(
+        include: impl IntoIterator<Item = &'a String>,
+        exclude: impl IntoIterator<Item = &'a String>,
+    ) -> Result<Self, Box<dyn std::error::Error>> {
+        Ok(PathFilter {
+            include: Patterns::new(include)?,
+            exclude: Patterns::new(exclude)?,
+        })
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.include.is_none() && self.exclude.is_none()
+    }
+
+    /// Returns true if the path should be processed. Include patterns only apply to files,
+    /// so that directories don't have to match them to be recursed into.
+    pub fn is_match(&self, path: &Path, is_dir: bool) -> bool {
+        if self.exclude.as_ref().is_some_and(|exclude| exclude.is_match(path)) {
+            return false;
+        }
+
+        is_dir || self.include.as_ref().is_none_or(|include| include.is_match(path))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn filter(include: &[&str], exclude: &[&str]) -> PathFilter {
+        let strings = |patterns: &[&str]| patterns.iter().map(|p| p.to_string()).collect::<Vec<_>>();
+
+        PathFilter::new(&strings(include), &strings(exclude)).unwrap()
+    }
+
+    #[test]
+    fn empty() {
+        let filter = filter(&[], &[]);
+
+        assert!(filter.is_empty());
+        assert!(filter.is_match(Path::new("/photos/a.png"), false));
+    }
+
+    #[test]
+    fn globs_and_regexes() {
+        let filter = filter(&["glob:**/raw/*.png", r"re:\.tiff?$"], &["regex:thumb"]);
+
+        assert!(filter.is_match(Path::new("/photos/raw/a.png"), false));
+        assert!(filter.is_match(Path::new("/photos/b.tif"), false));
+        assert!(!filter.is_match(Path::new("/photos/a.png"), false));
+        assert!(!filter.is_match(Path::new("/photos/raw/thumb.png"), false));
+
+        // directories are recursed into unless excluded
+        assert!(filter.is_match(Path::new("/photos"), true));
+        assert!(!filter.is_match(Path::new("/photos/thumbs"), true));
+    }
+
+    #[test]
+    fn invalid_patterns() {
+        let strings = |p: &str| vec![p.to_string()];
+
+        assert!(PathFilter::new(&strings("re:("), &[]).is_err());
+        assert!(PathFilter::new(&[], &strings("glob:[")).is_err());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn non_utf8_paths() {
+        use std::{ffi::OsStr, os::unix::ffi::OsStrExt};
+
+        let path = Path::new(OsStr::from_bytes(b"/photos/caf\xe9.png"));
+
+        assert!(filter(&[r"caf.\.png$"], &[]).is_match(path, false));
+        assert!(filter(&["glob:*.png"], &[]).is_match(path, false));
+        assert!(!filter(&[], &["glob:/photos/*"]).is_match(path, false));
+    }
+
+    #[cfg(windows)]
+    #[test]
+    fn windows_separators() {
+        let filter = filter(&["glob:**/raw/*.png"], &[]);
+
+        assert!(filter.is_match(Path::new(r"C:\photos\raw\a.png"), false));
+    }
+}
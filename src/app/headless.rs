@@ -0,0 +1,198 @@
+use std::{collections::HashSet, fs::OpenOptions, io::Write, sync::atomic::AtomicBool, time::Duration};
+
+use super::*;
+use crate::formatting::Bytes;
+
+const TICK: Duration = Duration::from_millis(100);
+
+/// Exit status after being stopped by a signal, as shells report it for SIGINT
+const SIGNALED: i32 = 130;
+
+/// Line describing the outcome of a file.
+fn outcome_line(file: &FileEntry, outcome: &ConversionOutcome) -> String {
+    let path = file.path.display();
+    let ratio = |input: u64, output: u64| output as f64 / input.max(1) as f64 * 100.0;
+
+    match outcome {
+        ConversionOutcome::Success(input, output) => format!(
+            "converted: {path} ({} -> {}, {:.02}%)",
+            Bytes(*input),
+            Bytes(*output),
+            ratio(*input, *output)
+        ),
+        ConversionOutcome::Warning(input, output, warning) => format!(
+            "warning: {path} ({} -> {}, {:.02}%): {warning}",
+            Bytes(*input),
+            Bytes(*output),
+            ratio(*input, *output)
+        ),
+        ConversionOutcome::Skipped => format!("skipped: {path}"),
+        ConversionOutcome::Inefficient(input, output) => format!(
+            "inefficient: {path} ({} -> {}, {:.02}%)",
+            Bytes(*input),
+            Bytes(*output),
+            ratio(*input, *output)
+        ),
+        ConversionOutcome::Error(e) => format!("failed: {path}: {e}"),
+    }
+}
+
+/// Prints the outcome of files that finished since the last call.
+/// Files finish out of order, so `next` is the first file not yet printed, and `printed` those after it that were.
+fn print_outcomes(conv: &ConversionState, next: &mut usize, printed: &mut HashSet<usize>) {
+    let handed_out = conv.idx.load(Ordering::Relaxed).min(conv.files.len());
+
+    for i in *next..handed_out {
+        if printed.contains(&i) {
+            continue;
+        }
+
+        if let Some(outcome) = conv.files[i].state.get() {
+            println!("{}", outcome_line(&conv.files[i], outcome));
+            printed.insert(i);
+        }
+    }
+
+    while printed.remove(next) {
+        *next += 1;
+    }
+}
+
+/// Prints the paths that could not be read while scanning since the last call, which the TUI lists under Errors.
+fn print_scan_errors(conv: &ConversionState, next: &mut usize) {
+    let len = conv.scan_errors.len();
+
+    for i in *next..len {
+        let scan::ScanError { path, error } = &conv.scan_errors[i];
+        println!("error: {}: {error}", path.display());
+    }
+
+    *next = len;
+}
+
+/// Runs without the interactive interface, printing a line for each file on stdout,
+/// and progress on stderr. Returns the exit status, nonzero if any file failed to convert
+/// or any path could not be read while scanning.
+pub fn run(app: App2) -> Result<i32, Box<dyn std::error::Error>> {
+    let signaled = Arc::new(AtomicBool::new(false));
+
+    for &signal in signal_hook::consts::TERM_SIGNALS {
+        // registered first, so it only exits on a signal after the first one
+        signal_hook::flag::register_conditional_shutdown(signal, SIGNALED, signaled.clone())?;
+        signal_hook::flag::register(signal, signaled.clone())?;
+    }
+
+    if let App2::Scanning { .. } = app {
+        eprintln!("Scanning...");
+    }
+
+    while matches!(app, App2::Scanning { .. }) && !app.scan_finished() {
+        // nothing to stop gracefully while scanning, so just quit
+        if signaled.load(Ordering::Relaxed) {
+            return Ok(SIGNALED);
+        }
+
+        std::thread::sleep(TICK);
+    }
+
+    let App2::Converting(app) = app.finish_scan().map_err(|e| format!("Failed to scan files: {e}"))? else {
+        return Err("Scanning did not finish".into());
+    };
+
+    let threads = app.spawn_workers();
+    let shared = &app.shared;
+
+    let mut summary_log = match shared.args.summary_log {
+        Some(ref path) => Some(
+            OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(path)
+                .map_err(|e| format!("Failed to open summary log '{}': {e}", path.display()))?,
+        ),
+        None => None,
+    };
+
+    let (mut next, mut printed, mut summarized) = (0, HashSet::new(), 0);
+    let mut next_scan_error = 0;
+    let mut stopping = false;
+
+    loop {
+        if !stopping && signaled.load(Ordering::Relaxed) {
+            stopping = true;
+            eprintln!("Stopping after the files in progress, signal again to stop right away");
+            shared.stop();
+        }
+
+        // checked before printing, so the last outcomes are printed before leaving
+        let completed = shared.conv.completed();
+
+        print_outcomes(&shared.conv, &mut next, &mut printed);
+        print_scan_errors(&shared.conv, &mut next_scan_error);
+
+        let now = shared.start.elapsed().as_millis() as u64;
+
+        if let Some(line) = shared.next_summary(&mut summarized, now) {
+            match summary_log {
+                Some(ref mut log) => {
+                    if let Err(e) = writeln!(log, "{line}") {
+                        eprintln!("Failed to write to summary log: {e}");
+                        summary_log = None;
+                    }
+                }
+                None => eprintln!("{line}"),
+            }
+        }
+
+        if completed {
+            break;
+        }
+
+        std::thread::sleep(TICK);
+    }
+
+    for thread in threads {
+        let _ = thread.join();
+    }
+
+    let conv = &shared.conv;
+
+    if let Some(error) = conv.scan_error.get() {
+        eprintln!("Scan failed: {error}");
+    }
+
+    if let Some(error) = conv.watch_error.get() {
+        eprintln!("Watch failed: {error}");
+    }
+
+    if let Some(error) = conv.log_error.get() {
+        eprintln!("{error}");
+    }
+
+    if let Some(reason) = conv.stop_reason.get() {
+        eprintln!("Stopped: {reason}");
+    }
+
+    if let Some(ref path) = shared.args.report {
+        report::write(shared, path, shared.args.report_format)
+            .map_err(|e| format!("Failed to write report '{}': {e}", path.display()))?;
+    }
+
+    let summary = shared.summary(shared.start.elapsed().as_millis() as u64);
+
+    // the summary line is left out if the last periodic one already printed covered every file
+    shared.print_final_summary(
+        &summary,
+        shared.args.summary_log.is_some()
+            || shared
+                .args
+                .summary_interval
+                .is_none_or(|_| summarized != summary.done()),
+    );
+
+    Ok(match (summary.errored + conv.scan_errors.len(), stopping) {
+        (1.., _) => 1,
+        (0, true) => SIGNALED,
+        (0, false) => 0,
+    })
+}
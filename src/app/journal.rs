@@ -0,0 +1,325 @@
+use std::{
+    collections::HashMap,
+    fs::File,
+    io::{BufRead, BufReader, Write},
+    path::Path,
+    sync::Mutex,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use serde::{Deserialize, Serialize};
+
+use super::FileEntry;
+use crate::cli::Conv2JxlArgs;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum JournalOutcome {
+    /// Conversion was started, but may not have finished
+    Started,
+    Converted,
+    Skipped,
+    Inefficient,
+    Failed,
+}
+
+impl JournalOutcome {
+    /// Name of the outcome, as written to the journal
+    pub fn name(self) -> &'static str {
+        match self {
+            JournalOutcome::Started => "started",
+            JournalOutcome::Converted => "converted",
+            JournalOutcome::Skipped => "skipped",
+            JournalOutcome::Inefficient => "inefficient",
+            JournalOutcome::Failed => "failed",
+        }
+    }
+}
+
+/// A line of the journal. Later lines for the same path replace earlier ones.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct JournalRecord {
+    /// Canonical path, lossily converted if it isn't valid UTF-8
+    pub path: String,
+    /// Raw bytes of the canonical path, only if it isn't valid UTF-8
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub path_bytes: Option<Vec<u8>>,
+    pub size: u64,
+    /// Modification time in nanoseconds since the Unix epoch
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub mtime: Option<u64>,
+    /// BLAKE3 hash of the content, with --journal-hash
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub hash: Option<String>,
+    /// Options that affect the outcome, see [`settings`]
+    pub settings: String,
+    pub outcome: JournalOutcome,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub input: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub output: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub message: Option<String>,
+    /// When the record was written, in seconds since the Unix epoch
+    pub time: u64,
+}
+
+/// Append-only record of outcomes across runs, keyed by path, size and modification time.
+pub struct Journal {
+    file: Mutex<File>,
+    /// Last record for each path, keyed by the raw bytes of the canonical path
+    known: HashMap<Vec<u8>, JournalRecord>,
+    /// Settings of this run, see [`settings`]
+    settings: String,
+    hash: bool,
+}
+
+/// Summarizes the options that affect the outcome of a conversion,
+/// so outcomes are only reused under the same settings.
+fn settings(args: &Conv2JxlArgs) -> String {
+    let optional = |value: Option<String>| value.unwrap_or_else(|| "-".to_owned());
+
+    format!(
+        "q={} e={} j={} r={} R={} Q={} I={} n={}",
+        args.quality,
+        args.effort,
+        args.lossless_jpeg as u8,
+        !args.disable_jpeg_reconstruction as u8,
+        args.min_ratio,
+        optional(args.quality_if_inefficient.map(|q| q.to_string())),
+        optional(args.min_inefficient_size.map(|size| size.to_string())),
+        args.no_preserve_extension as u8,
+    )
+}
+
+fn modified(metadata: &std::fs::Metadata) -> Option<u64> {
+    let since_epoch = metadata.modified().ok()?.duration_since(UNIX_EPOCH).ok()?;
+
+    u64::try_from(since_epoch.as_nanos()).ok()
+}
+
+/// Hashes the content of the file at `path` with BLAKE3, which is the same on every platform and version.
+fn hash_file(path: &Path) -> std::io::Result<String> {
+    let mut hasher = blake3::Hasher::new();
+    hasher.update_reader(File::open(path)?)?;
+
+    Ok(hasher.finalize().to_hex().to_string())
+}
+
+/// Raw bytes of the canonical path of `path`, so that the same file is found however it was given,
+/// and paths that aren't valid UTF-8 can't collide. Falls back to the path as is if it no longer exists.
+fn key(path: &Path) -> Vec<u8> {
+    path.canonicalize()
+        .unwrap_or_else(|_| path.to_owned())
+        .into_os_string()
+        .into_encoded_bytes()
+}
+
+impl Journal {
+    /// Opens the journal at `path` for appending, creating it if needed, and loads the outcomes already in it.
+    pub fn open(path: &Path, args: &Conv2JxlArgs) -> std::io::Result<Journal> {
+        let file = std::fs::OpenOptions::new()
+            .read(true)
+            .append(true)
+            .create(true)
+            .open(path)?;
+
+        let mut known = HashMap::new();
+
+        for line in BufReader::new(&file).lines() {
+            // a run that was killed may have left the last line incomplete
+            if let Ok(record) = serde_json::from_str::<JournalRecord>(&line?) {
+                let key = match record.path_bytes {
+                    Some(ref bytes) => bytes.clone(),
+                    None => record.path.clone().into_bytes(),
+                };
+
+                known.insert(key, record);
+            }
+        }
+
+        Ok(Journal {
+            file: Mutex::new(file),
+            known,
+            settings: settings(args),
+            hash: args.journal_hash,
+        })
+    }
+
+    /// Returns the last record for `file` from earlier runs if it is still about the same content.
+    fn lookup(&self, file: &FileEntry, hash: Option<&str>) -> Option<&JournalRecord> {
+        let record = self.known.get(&key(&file.path))?;
+
+        let same = record.size == file.metadata.len()
+            && record.mtime == modified(&file.metadata)
+            && (!self.hash || record.hash.as_deref() == hash);
+
+        same.then_some(record)
+    }
+
+    /// Hashes the content of `file` if hashes are recorded.
+    pub fn hash(&self, file: &FileEntry) -> Option<String> {
+        self.hash.then(|| hash_file(&file.path).ok()).flatten()
+    }
+
+    /// Returns the outcome from an earlier run with the same settings
+    /// if `file` was found inefficient or failed to convert, so it can be skipped.
+    pub fn known_outcome(&self, file: &FileEntry, hash: Option<&str>) -> Option<&JournalRecord> {
+        self.lookup(file, hash).filter(|record| {
+            record.settings == self.settings
+                && matches!(record.outcome, JournalOutcome::Inefficient | JournalOutcome::Failed)
+        })
+    }
+
+    /// Returns true if an earlier run started converting `file` and never recorded the outcome,
+    /// in which case its output may be incomplete.
+    pub fn interrupted(&self, file: &FileEntry, hash: Option<&str>) -> bool {
+        self.lookup(file, hash)
+            .is_some_and(|record| record.outcome == JournalOutcome::Started)
+    }
+
+    /// Appends a record for `file`.
+    pub fn record(
+        &self,
+        file: &FileEntry,
+        hash: Option<String>,
+        outcome: JournalOutcome,
+        sizes: Option<(u64, u64)>,
+        message: Option<String>,
+    ) -> std::io::Result<()> {
+        let key = key(&file.path);
+
+        let (path, path_bytes) = match String::from_utf8(key) {
+            Ok(path) => (path, None),
+            Err(e) => (String::from_utf8_lossy(e.as_bytes()).into_owned(), Some(e.into_bytes())),
+        };
+
+        let record = JournalRecord {
+            path,
+            path_bytes,
+            size: file.metadata.len(),
+            mtime: modified(&file.metadata),
+            hash,
+            settings: self.settings.clone(),
+            outcome,
+            input: sizes.map(|(input, _)| input),
+            output: sizes.map(|(_, output)| output),
+            message,
+            time: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map_or(0, |time| time.as_secs()),
+        };
+
+        let mut line = serde_json::to_string(&record).map_err(std::io::Error::other)?;
+        line.push('\n');
+
+        // a single write per record, so records from different threads never interleave
+        self.file.lock().unwrap().write_all(line.as_bytes())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use super::*;
+    use crate::cli::FileType;
+
+    fn args(args: &[&str]) -> Conv2JxlArgs {
+        argh::FromArgs::from_args(&["conv2jxl"], args).unwrap()
+    }
+
+    fn entry(path: PathBuf) -> FileEntry {
+        let metadata = std::fs::metadata(&path).unwrap();
+
+        FileEntry::new(path, FileType::PNG, Some(FileType::PNG), metadata)
+    }
+
+    #[test]
+    fn hashes_are_stable() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("a.png");
+
+        std::fs::write(&path, b"abc").unwrap();
+        assert_eq!(
+            hash_file(&path).unwrap(),
+            "6437b3ac38465133ffb63b75273a8db548c558465d79db03fd359c6cd5bd9d85"
+        );
+
+        // larger than any read buffer, so chunk boundaries can't matter
+        let data = Vec::from_iter((0..1_000_000u32).map(|i| i as u8));
+        std::fs::write(&path, &data).unwrap();
+        assert_eq!(hash_file(&path).unwrap(), blake3::hash(&data).to_hex().to_string());
+    }
+
+    #[test]
+    fn outcomes_survive_reopening() {
+        let dir = tempfile::tempdir().unwrap();
+        let journal_path = dir.path().join("journal.jsonl");
+        let hashing = args(&["--journal-hash"]);
+
+        std::fs::create_dir(dir.path().join("sub")).unwrap();
+        let path = dir.path().join("sub/a.png");
+        std::fs::write(&path, b"png").unwrap();
+
+        let journal = Journal::open(&journal_path, &hashing).unwrap();
+        let file = entry(path.clone());
+        let hash = journal.hash(&file);
+
+        journal
+            .record(
+                &file,
+                hash.clone(),
+                JournalOutcome::Failed,
+                None,
+                Some("bad".to_owned()),
+            )
+            .unwrap();
+
+        let journal = Journal::open(&journal_path, &hashing).unwrap();
+
+        // found under another spelling of the same path
+        let other = entry(dir.path().join("sub/../sub/./a.png"));
+        let record = journal.known_outcome(&other, hash.as_deref()).unwrap();
+        assert_eq!(record.message.as_deref(), Some("bad"));
+
+        // but not once the content changed
+        assert!(journal.known_outcome(&other, Some("0")).is_none());
+
+        // nor under other settings
+        let journal = Journal::open(&journal_path, &args(&["-q", "50"])).unwrap();
+        assert!(journal.known_outcome(&other, None).is_none());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn non_utf8_paths_dont_collide() {
+        use std::{ffi::OsStr, os::unix::ffi::OsStrExt};
+
+        let dir = tempfile::tempdir().unwrap();
+        let journal_path = dir.path().join("journal.jsonl");
+        let defaults = args(&[]);
+
+        // both would be "caf\u{fffd}.png" if converted lossily
+        let (a, b) = (
+            dir.path().join(OsStr::from_bytes(b"caf\xe9.png")),
+            dir.path().join(OsStr::from_bytes(b"caf\xff.png")),
+        );
+
+        for path in [&a, &b] {
+            if std::fs::write(path, b"png").is_err() {
+                return; // the filesystem only takes UTF-8 names
+            }
+        }
+
+        let journal = Journal::open(&journal_path, &defaults).unwrap();
+        journal
+            .record(&entry(a.clone()), None, JournalOutcome::Failed, None, None)
+            .unwrap();
+
+        let journal = Journal::open(&journal_path, &defaults).unwrap();
+
+        assert!(journal.known_outcome(&entry(a), None).is_some());
+        assert!(journal.known_outcome(&entry(b), None).is_none());
+    }
+}
@@ -1,20 +1,39 @@
 use std::{
     borrow::Cow,
     cmp::Reverse,
-    collections::BTreeSet,
-    path::PathBuf,
+    collections::{BTreeMap, BTreeSet, VecDeque},
+    path::{Path, PathBuf},
     sync::{
         Arc, Condvar, Mutex, OnceLock, RwLock,
-        atomic::{AtomicU64, AtomicUsize, Ordering},
+        atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering},
     },
+    thread::JoinHandle,
     time::Instant,
 };
 
 use ratatui::style::Color;
 
-use crate::cli::{Conv2JxlArgs, FileType, PerFileType};
+use crate::{
+    cli::{Conv2JxlArgs, FileType, PerFileType},
+    formatting::{Bytes, DecimalTime, Speed, TimeBreakdown},
+    utils::AppendVec,
+};
 
+pub mod archive;
 pub mod conv2png;
+pub mod detect;
+pub mod error_log;
+pub mod estimate;
+pub mod expr;
+pub mod filter;
+pub mod headless;
+pub mod journal;
+pub mod probe;
+pub mod report;
+pub mod restore;
+
+/// Number of --summary-interval lines kept for the history panel
+pub const HISTORY_LEN: usize = 4;
 
 pub enum ConversionOutcome {
     Success(u64, u64),                    // input size, output size
@@ -28,18 +47,63 @@ pub struct FileEntry {
     pub state: OnceLock<ConversionOutcome>,
     pub last_active: AtomicU64,
     pub path: PathBuf,
+    /// The type the file is converted as
     pub ext: FileType,
+    /// The type implied by the file extension, if any, which may differ from `ext` when detecting by content
+    pub named: Option<FileType>,
     pub metadata: std::fs::Metadata,
+    /// Other hardlinks to the same file, which get a link to the output as well (--hardlinks all)
+    pub links: Vec<PathBuf>,
+    /// Properties from the image header, if it was read while scanning
+    pub info: Option<probe::ImageInfo>,
+    /// Quality of the last attempt at converting the file
+    pub quality: OnceLock<u8>,
+    /// Time spent converting the file in milliseconds, over all attempts
+    pub elapsed: AtomicU64,
 }
 
 impl FileEntry {
-    pub fn new(path: PathBuf, ext: FileType, metadata: std::fs::Metadata) -> Self {
+    pub fn new(path: PathBuf, ext: FileType, named: Option<FileType>, metadata: std::fs::Metadata) -> Self {
         Self {
             state: OnceLock::new(),
             last_active: AtomicU64::new(0),
             path,
             ext,
+            named,
             metadata,
+            links: Vec::new(),
+            info: None,
+            quality: OnceLock::new(),
+            elapsed: AtomicU64::new(0),
+        }
+    }
+
+    pub fn with_info(mut self, info: Option<probe::ImageInfo>) -> Self {
+        self.info = info;
+        self
+    }
+
+    /// Returns the type implied by the extension if it doesn't match the detected content.
+    pub fn mismatch(&self) -> Option<FileType> {
+        self.named.filter(|&named| !named.is_compatible(self.ext))
+    }
+
+    /// Path of the converted file, named after the extension rather than the content
+    /// so that output names don't depend on how the type was detected.
+    pub fn output_path(&self, no_preserve_extension: bool) -> PathBuf {
+        self.output_path_for(&self.path, no_preserve_extension)
+    }
+
+    /// Path of the converted file next to `path`, which may be another link to this file.
+    /// Archives are converted in place.
+    pub fn output_path_for(&self, path: &Path, no_preserve_extension: bool) -> PathBuf {
+        if self.ext == FileType::ZIP {
+            return path.to_owned();
+        }
+
+        match no_preserve_extension {
+            false => path.with_extension(format!("{}.jxl", self.named.unwrap_or(self.ext))),
+            true => path.with_extension("jxl"),
         }
     }
 
@@ -53,7 +117,8 @@ impl FileEntry {
 
 #[derive(Default)]
 pub struct ConversionProgress {
-    pub total: usize,
+    /// Total files to process, which may still grow while streaming
+    pub total: AtomicUsize,
     /// Files successfully processed
     pub processed: AtomicUsize,
     /// Files that encountered errors during processing
@@ -90,15 +155,59 @@ impl ConversionProgress {
     }
 }
 
+/// Why no more files were handed out before all were converted.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StopReason {
+    TimeBudget,
+    ByteBudget,
+}
+
+impl std::fmt::Display for StopReason {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            StopReason::TimeBudget => "time budget reached",
+            StopReason::ByteBudget => "byte budget reached",
+        })
+    }
+}
+
 pub struct ThreadState {
     pub file_idx: AtomicUsize,
     pub start_time: AtomicU64, // in milliseconds since program start
 }
 
 pub struct ConversionState {
-    pub excluded: usize,
-    pub files: Vec<FileEntry>,
+    pub excluded: AtomicUsize,
+    /// Files skipped because they were modified too recently
+    pub recent: AtomicUsize,
+    pub files: AppendVec<FileEntry>,
     pub idx: AtomicUsize,
+    /// Set once all files have been found, which may happen after conversion has started when streaming.
+    /// Until then, totals are provisional.
+    pub scan_complete: AtomicBool,
+    /// Set while --watch may still find more files after the scan completes
+    pub watching: AtomicBool,
+    /// Error that ended a streaming scan early
+    pub scan_error: OnceLock<String>,
+    /// Error that stopped --watch
+    pub watch_error: OnceLock<String>,
+    /// Watcher for --watch, started along with the scan and taken by the thread handling its events
+    pub watch: Mutex<Option<scan::Watch>>,
+    /// Outcomes across runs, with --journal
+    pub journal: Option<journal::Journal>,
+    /// Outcomes of this run, with --error-log
+    pub error_log: Option<error_log::ErrorLog>,
+    /// First error writing to the journal or the error log
+    pub log_error: OnceLock<String>,
+    /// Paths that could not be read while scanning, and were skipped
+    pub scan_errors: AppendVec<scan::ScanError>,
+    /// Files found per device, available once scanning completes
+    pub devices: Mutex<BTreeMap<u64, scan::DeviceSummary>>,
+    pub stopping: AtomicBool,
+    /// Set if a budget stopped the run early
+    pub stop_reason: OnceLock<StopReason>,
+    /// Input bytes of the files handed out for conversion so far, for --byte-budget
+    pub claimed_bytes: AtomicU64,
     /// Pre-allocated slots for active threads to update
     pub active: Vec<ThreadState>,
     /// indices of files that encountered errors or inefficiencies during processing,
@@ -108,22 +217,333 @@ pub struct ConversionState {
     pub paused: Arc<(Mutex<bool>, Condvar)>,
 }
 
+impl ConversionState {
+    pub fn new(parallel: usize) -> Self {
+        ConversionState {
+            excluded: AtomicUsize::new(0),
+            recent: AtomicUsize::new(0),
+            files: AppendVec::default(),
+            idx: AtomicUsize::new(0),
+            scan_complete: AtomicBool::new(false),
+            watching: AtomicBool::new(false),
+            scan_error: OnceLock::new(),
+            watch_error: OnceLock::new(),
+            watch: Mutex::new(None),
+            journal: None,
+            error_log: None,
+            log_error: OnceLock::new(),
+            scan_errors: AppendVec::default(),
+            devices: Mutex::default(),
+            stopping: AtomicBool::new(false),
+            stop_reason: OnceLock::new(),
+            claimed_bytes: AtomicU64::new(0),
+            active: Vec::from_iter((0..parallel).map(|_| ThreadState {
+                file_idx: AtomicUsize::new(usize::MAX),
+                start_time: AtomicU64::new(0),
+            })),
+            non_success: Default::default(),
+            progress: PerFileType::default(),
+            paused: Default::default(),
+        }
+    }
+
+    /// Adds a file to be converted, updating the totals.
+    pub fn push(&self, file: FileEntry) {
+        let progress = self.progress.get(file.ext);
+
+        progress.total.fetch_add(1, Ordering::Relaxed);
+        progress.total_bytes.fetch_add(file.metadata.len(), Ordering::Relaxed);
+
+        self.files.push(file);
+    }
+
+    /// Hands over the watcher started with the scan, or the error starting it, for --watch.
+    fn set_watch(&mut self, watch: Option<Result<scan::Watch, String>>) {
+        match watch {
+            Some(Ok(watch)) => *self.watch.get_mut().unwrap() = Some(watch),
+            Some(Err(e)) => {
+                let _ = self.watch_error.set(e);
+            }
+            None => {}
+        }
+    }
+}
+
 pub struct SharedState {
     pub args: Conv2JxlArgs,
     pub conv: ConversionState,
     pub start: Instant,
 }
 
+/// Progress aggregated over all file types, with estimates for the rest of the run.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct Summary {
+    pub total_files: usize,
+    /// Set while still scanning, when totals are lower bounds
+    pub provisional: bool,
+    pub processed: usize,
+    pub errored: usize,
+    pub inefficient: usize,
+    pub total_bytes: u64,
+    pub input_bytes: u64,
+    pub output_bytes: u64,
+    /// Time spent converting in milliseconds, summed over threads
+    pub elapsed: u64,
+    /// Time since the start of the run in milliseconds
+    pub real_elapsed: f64,
+    /// Remaining time in milliseconds, as if converted one at a time
+    pub estimated_eta: f64,
+    pub estimated_savings: u64,
+    /// Fraction of the input bytes processed
+    pub progress: f64,
+}
+
+impl Summary {
+    /// Files done, whatever the outcome
+    pub fn done(&self) -> usize {
+        self.processed + self.errored + self.inefficient
+    }
+
+    /// Output size as a percentage of the input size
+    pub fn compression_ratio(&self) -> f64 {
+        if self.input_bytes > 0 {
+            self.output_bytes as f64 / self.input_bytes as f64 * 100.0
+        } else {
+            0.0
+        }
+    }
+}
+
+impl SharedState {
+    /// Aggregates the progress of each file type, `now` being milliseconds since the start of the run.
+    pub fn summary(&self, now: u64) -> Summary {
+        let mut summary = Summary {
+            total_files: self.conv.files.len(),
+            provisional: !self.conv.scan_complete.load(Ordering::Relaxed),
+            real_elapsed: self.start.elapsed().as_millis() as f64,
+            ..Summary::default()
+        };
+
+        // for each file type, aggregate the stats and estimate the overall ETA and savings
+        for (_ft, progress) in self.conv.progress.iter() {
+            let current_total_bytes = progress.total_bytes.load(Ordering::Acquire);
+
+            // failed and inefficient files are taken out of the total bytes, so count them first
+            summary.processed += progress.processed.load(Ordering::Relaxed);
+            summary.errored += progress.errored.load(Ordering::Relaxed);
+            summary.inefficient += progress.inefficient.load(Ordering::Relaxed);
+
+            if current_total_bytes == 0 {
+                continue;
+            }
+
+            let current_input_bytes = progress.input_bytes.load(Ordering::Relaxed);
+            let current_output_bytes = progress.output_bytes.load(Ordering::Relaxed);
+            let current_elapsed = progress.elapsed.load(Ordering::Relaxed);
+
+            summary.total_bytes += current_total_bytes;
+            summary.input_bytes += current_input_bytes;
+            summary.output_bytes += current_output_bytes;
+            summary.elapsed += current_elapsed;
+
+            let remaining_bytes = current_total_bytes.saturating_sub(current_input_bytes);
+
+            if current_elapsed > 0 {
+                let current_speed = current_input_bytes as f64 / current_elapsed as f64;
+
+                // add remaining time for this file type to the overall ETA
+                summary.estimated_eta += remaining_bytes as f64 / current_speed;
+            }
+
+            let current_compression_ratio = if current_input_bytes > 0 {
+                current_output_bytes as f64 / current_input_bytes as f64
+            } else {
+                0.0
+            };
+
+            // estimate savings for remaining bytes based on current compression ratio
+            summary.estimated_savings += ((1.0 - current_compression_ratio) * remaining_bytes as f64) as u64
+                + (current_input_bytes - current_output_bytes);
+        }
+
+        // subtract time already spent on files in progress
+        for thread in &self.conv.active {
+            let start_time = thread.start_time.load(Ordering::Relaxed);
+            summary.estimated_eta -= now.saturating_sub(start_time) as f64;
+        }
+
+        if summary.total_bytes == 0 {
+            summary.progress = 1.0;
+            summary.estimated_eta = 0.0;
+        } else {
+            summary.progress = summary.input_bytes as f64 / summary.total_bytes as f64;
+        }
+
+        summary
+    }
+
+    /// Returns a line summarizing progress once another --summary-interval files are done since `last`,
+    /// which is then updated.
+    pub fn next_summary(&self, last: &mut usize, now: u64) -> Option<String> {
+        let interval = self.args.summary_interval.filter(|&interval| interval > 0)?;
+        let summary = self.summary(now);
+
+        if summary.done() / interval <= *last / interval {
+            return None;
+        }
+
+        *last = summary.done();
+
+        Some(self.summary_line(&summary))
+    }
+
+    /// Prints what the run skipped while scanning, the summary if `with_line` is set,
+    /// and the seed used, so that a randomized run can be repeated.
+    pub fn print_final_summary(&self, summary: &Summary, with_line: bool) {
+        let conv = &self.conv;
+        let (excluded, recent) = (
+            conv.excluded.load(Ordering::Relaxed),
+            conv.recent.load(Ordering::Relaxed),
+        );
+
+        if excluded > 0 || recent > 0 {
+            eprintln!("Excluded: {excluded} | Too recent: {recent}");
+        }
+
+        if with_line {
+            eprintln!("{}", self.summary_line(summary));
+        }
+
+        if let Some(seed) = self.args.seed {
+            eprintln!("Seed: {seed} (pass --seed {seed} to repeat this run)");
+        }
+    }
+
+    /// One line summarizing progress, for --summary-interval.
+    pub fn summary_line(&self, summary: &Summary) -> String {
+        let provisional = if summary.provisional { "+" } else { "" };
+        let parallel = self.args.parallel.max(1) as f64;
+
+        format!(
+            "Processed: {}/{}{provisional} | Errored: {} | Inefficient: {} | In: {} | Out: {} ({:.02}%) | Elapsed: {} | Speed: {} | ETA: {}{provisional}",
+            summary.done(),
+            summary.total_files,
+            summary.errored,
+            summary.inefficient,
+            Bytes(summary.input_bytes),
+            Bytes(summary.output_bytes),
+            summary.compression_ratio(),
+            TimeBreakdown(summary.real_elapsed),
+            Speed::new(summary.input_bytes, summary.elapsed as f64 / parallel),
+            DecimalTime(summary.estimated_eta / parallel),
+        )
+    }
+}
+
+#[allow(clippy::large_enum_variant)] // only one instance exists
 pub enum App2 {
     Started(Conv2JxlArgs), // initial state, before scanning
     Scanning {
-        args: Conv2JxlArgs,
+        args: Arc<Conv2JxlArgs>,
         observer: Arc<scan::ScanObserver>,
+        scanner: JoinHandle<Result<ConversionState, String>>,
+        journal: Option<journal::Journal>,
+        error_log: Option<error_log::ErrorLog>,
+        watch: Option<Result<scan::Watch, String>>,
+        ui_state: ScanningUIState,
     },
-    Converting {
-        shared: Arc<SharedState>,
-        ui_state: ConvertingUIState,
-    },
+    Converting(App),
+}
+
+impl App2 {
+    /// Starts scanning for files in a background thread,
+    /// with the files for --journal and --error-log already opened.
+    pub fn start_scan(self, journal: Option<journal::Journal>, error_log: Option<error_log::ErrorLog>) -> Self {
+        let App2::Started(args) = self else {
+            return self;
+        };
+
+        let watch = args.watch.then(|| scan::Watch::start(&args).map_err(|e| e.to_string()));
+
+        if args.streaming() {
+            let mut conv = ConversionState::new(args.parallel as usize);
+
+            conv.journal = journal;
+            conv.error_log = error_log;
+            conv.set_watch(watch);
+
+            let shared = Arc::new(SharedState {
+                conv,
+                args,
+                start: Instant::now(),
+            });
+
+            std::thread::spawn({
+                let shared = shared.clone();
+
+                move || shared.scan_streaming(&scan::ScanObserver::default())
+            });
+
+            return App2::Converting(App::new(shared));
+        }
+
+        let args = Arc::new(args);
+        let observer = Arc::new(scan::ScanObserver::default());
+
+        let scanner = {
+            let (args, observer) = (args.clone(), observer.clone());
+
+            std::thread::spawn(move || args.scan(&observer).map_err(|e| e.to_string()))
+        };
+
+        App2::Scanning {
+            args,
+            observer,
+            scanner,
+            journal,
+            error_log,
+            watch,
+            ui_state: ScanningUIState {
+                list_offset: 0,
+                time: 0,
+                start: Instant::now(),
+            },
+        }
+    }
+
+    pub fn scan_finished(&self) -> bool {
+        matches!(self, App2::Scanning { scanner, .. } if scanner.is_finished())
+    }
+
+    /// Switches to converting once scanning has finished, or returns the scan error.
+    pub fn finish_scan(self) -> Result<Self, String> {
+        let App2::Scanning {
+            args,
+            scanner,
+            journal,
+            error_log,
+            watch,
+            ..
+        } = self
+        else {
+            return Ok(self);
+        };
+
+        let mut conv = scanner.join().map_err(|_| "Scanning thread panicked".to_owned())??;
+
+        conv.journal = journal;
+        conv.error_log = error_log;
+        conv.set_watch(watch);
+
+        // the scanning thread has exited, so this is the last reference
+        let args = Arc::into_inner(args).expect("Arguments still shared after scanning");
+
+        Ok(App2::Converting(App::new(Arc::new(SharedState {
+            args,
+            conv,
+            start: Instant::now(),
+        }))))
+    }
 }
 
 pub struct App {
@@ -132,6 +552,44 @@ pub struct App {
 }
 
 impl App {
+    pub fn new(shared: Arc<SharedState>) -> Self {
+        App {
+            ui_state: ConvertingUIState {
+                list_offset: 0,
+                last_processing: vec![usize::MAX; shared.args.parallel as usize],
+                time: 0,
+
+                file_tab: FileTab::Files,
+                details: false,
+
+                history: VecDeque::with_capacity(HISTORY_LEN),
+                summarized: 0,
+            },
+
+            shared,
+        }
+    }
+
+    /// Spawns the conversion worker threads, and the watcher thread for --watch.
+    pub fn spawn_workers(&self) -> Vec<JoinHandle<()>> {
+        if self.shared.args.watch {
+            // must be set before the workers start, so they don't exit once the initial files run out
+            self.shared.conv.watching.store(true, Ordering::Release);
+
+            let shared = self.shared.clone();
+
+            std::thread::spawn(move || shared.watch());
+        }
+
+        Vec::from_iter((0..self.shared.args.parallel as usize).map(|i| {
+            let shared = self.shared.clone();
+
+            std::thread::spawn(move || {
+                shared.run(i);
+            })
+        }))
+    }
+
     pub fn add_offset(&mut self, offset: i32) {
         if offset < 0 {
             self.ui_state.list_offset = self.ui_state.list_offset.saturating_sub((-offset) as usize);
@@ -185,6 +643,12 @@ pub struct ConvertingUIState {
     pub file_tab: FileTab,
 
     pub details: bool,
+
+    /// The last lines printed every --summary-interval files, oldest first
+    pub history: VecDeque<String>,
+
+    /// Files done as of the last line in the history
+    pub summarized: usize,
 }
 
 impl FileTab {
@@ -252,3 +716,55 @@ impl FileTab {
 pub mod convert;
 pub mod render;
 pub mod scan;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn shared() -> SharedState {
+        SharedState {
+            args: argh::FromArgs::from_args(&["conv2jxl"], &[]).unwrap(),
+            conv: ConversionState::new(1),
+            start: Instant::now(),
+        }
+    }
+
+    #[test]
+    fn summary_counts_files_of_types_with_no_bytes_left() {
+        let shared = shared();
+        let progress = shared.conv.progress.get(FileType::PNG);
+
+        progress.total.store(2, Ordering::Relaxed);
+        progress.total_bytes.store(300, Ordering::Relaxed);
+
+        // every file of the type failed or was inefficient, leaving no bytes to convert
+        progress.errored(100);
+        progress.inefficient(200);
+
+        let summary = shared.summary(0);
+
+        assert_eq!(summary.errored, 1);
+        assert_eq!(summary.inefficient, 1);
+        assert_eq!(summary.done(), 2);
+        assert_eq!(summary.total_bytes, 0);
+    }
+
+    #[test]
+    fn summary_counts_across_types() {
+        let shared = shared();
+
+        let png = shared.conv.progress.get(FileType::PNG);
+        png.total_bytes.store(100, Ordering::Relaxed);
+        png.add(100, 40, 10);
+
+        let jpeg = shared.conv.progress.get(FileType::JPEG);
+        jpeg.total_bytes.store(50, Ordering::Relaxed);
+        jpeg.errored(50);
+
+        let summary = shared.summary(0);
+
+        assert_eq!((summary.processed, summary.errored), (1, 1));
+        assert_eq!((summary.input_bytes, summary.output_bytes), (100, 40));
+        assert_eq!(summary.progress, 1.0);
+    }
+}
@@ -0,0 +1,589 @@
+use std::{
+    fs::File,
+    io::{BufRead, BufReader, Read, Seek},
+    path::Path,
+};
+
+use image::{ExtendedColorType, ImageDecoder};
+
+use crate::cli::FileType;
+
+/// How the color of an image is stored.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ColorKind {
+    Gray,
+    GrayAlpha,
+    Rgb,
+    Rgba,
+    Palette,
+    Cmyk,
+}
+
+/// Properties of an image read from its header, without decoding it.
+///
+/// Properties that can't be told from the header of a format are `None`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct ImageInfo {
+    pub width: u32,
+    pub height: u32,
+    /// Bits per channel, which for palette images is the depth of the palette entries
+    pub bit_depth: Option<u32>,
+    /// Number of channels, after expanding any palette
+    pub channels: Option<u32>,
+    pub has_alpha: Option<bool>,
+    pub animated: Option<bool>,
+    pub color: Option<ColorKind>,
+}
+
+type ProbeResult = Result<ImageInfo, Box<dyn std::error::Error>>;
+
+/// Reads the properties of the image at `path`, which is of type `ftype`.
+pub fn probe(path: &Path, ftype: FileType) -> ProbeResult {
+    let mut file = BufReader::new(File::open(path)?);
+
+    match ftype {
+        FileType::PNG | FileType::APNG => probe_png(&mut file),
+        FileType::GIF => probe_gif(&mut file),
+        FileType::JPEG => probe_jpeg(&mut file),
+        // any of these may hold the others, so go by the magic number instead of the type
+        FileType::PPM | FileType::PNM | FileType::PAM | FileType::PFM => probe_netpbm(&mut file),
+        FileType::PGX => probe_pgx(&mut file),
+        FileType::TIFF => from_decoder(image::codecs::tiff::TiffDecoder::new(file)?),
+        FileType::TGA => from_decoder(image::codecs::tga::TgaDecoder::new(file)?),
+        FileType::QOI => from_decoder(image::codecs::qoi::QoiDecoder::new(file)?),
+        FileType::BMP => from_decoder(image::codecs::bmp::BmpDecoder::new(file)?),
+        FileType::JXL => {
+            let size = imagesize::size(path)?;
+
+            Ok(ImageInfo {
+                width: size.width as u32,
+                height: size.height as u32,
+                ..ImageInfo::default()
+            })
+        }
+        FileType::ZIP => Err("Archives have no image header".into()),
+    }
+}
+
+fn color_kind(channels: u32, has_alpha: bool) -> ColorKind {
+    match (channels, has_alpha) {
+        (1, _) => ColorKind::Gray,
+        (2, _) => ColorKind::GrayAlpha,
+        (4, false) => ColorKind::Cmyk,
+        (4, true) => ColorKind::Rgba,
+        _ => ColorKind::Rgb,
+    }
+}
+
+fn from_decoder(decoder: impl ImageDecoder) -> ProbeResult {
+    let (width, height) = decoder.dimensions();
+    let color = decoder.original_color_type();
+
+    let channels = color.channel_count() as u32;
+    let has_alpha =
+        !matches!(color, ExtendedColorType::Cmyk8 | ExtendedColorType::Cmyk16) && channels.is_multiple_of(2);
+
+    Ok(ImageInfo {
+        width,
+        height,
+        bit_depth: Some(color.bits_per_pixel() as u32 / channels),
+        channels: Some(channels),
+        has_alpha: Some(has_alpha),
+        animated: Some(false),
+        color: Some(color_kind(channels, has_alpha)),
+    })
+}
+
+/// Walks the chunks up to the first `IDAT`, skipping their data.
+fn probe_png(file: &mut BufReader<impl Read + Seek>) -> ProbeResult {
+    let mut header = [0u8; 8 + 8 + 13];
+    file.read_exact(&mut header)?;
+
+    let ihdr = &header[16..];
+
+    if &header[12..16] != b"IHDR" {
+        return Err("missing IHDR chunk".into());
+    }
+
+    let width = u32::from_be_bytes([ihdr[0], ihdr[1], ihdr[2], ihdr[3]]);
+    let height = u32::from_be_bytes([ihdr[4], ihdr[5], ihdr[6], ihdr[7]]);
+    let depth = ihdr[8] as u32;
+    let color_type = ihdr[9];
+
+    file.seek_relative(4)?; // IHDR crc
+
+    let mut transparency = false;
+    let mut animated = false;
+
+    loop {
+        let mut chunk = [0u8; 8];
+        file.read_exact(&mut chunk)?;
+
+        let len = u32::from_be_bytes([chunk[0], chunk[1], chunk[2], chunk[3]]);
+
+        match &chunk[4..] {
+            b"tRNS" => transparency = true,
+            // only counts as animated if there is more than one frame
+            b"acTL" => {
+                let mut num_frames = [0u8; 4];
+                file.read_exact(&mut num_frames)?;
+                file.seek_relative(len as i64)?; // remaining data and crc
+
+                animated = u32::from_be_bytes(num_frames) > 1;
+                continue;
+            }
+            b"IDAT" | b"IEND" => break,
+            _ => {}
+        }
+
+        file.seek_relative(len as i64 + 4)?;
+    }
+
+    let (color, channels) = match color_type {
+        0 if transparency => (ColorKind::GrayAlpha, 2),
+        0 => (ColorKind::Gray, 1),
+        2 if transparency => (ColorKind::Rgba, 4),
+        2 => (ColorKind::Rgb, 3),
+        3 => (ColorKind::Palette, if transparency { 4 } else { 3 }),
+        4 => (ColorKind::GrayAlpha, 2),
+        6 => (ColorKind::Rgba, 4),
+        _ => return Err(format!("invalid PNG color type {color_type}").into()),
+    };
+
+    Ok(ImageInfo {
+        width,
+        height,
+        bit_depth: Some(if color_type == 3 { 8 } else { depth }),
+        channels: Some(channels),
+        has_alpha: Some(transparency || matches!(color_type, 4 | 6)),
+        animated: Some(animated),
+        color: Some(color),
+    })
+}
+
+/// Skips GIF data sub-blocks, up to and including the terminating empty block.
+fn skip_sub_blocks(file: &mut BufReader<impl Read + Seek>) -> std::io::Result<()> {
+    loop {
+        let mut len = [0u8; 1];
+        file.read_exact(&mut len)?;
+
+        if len[0] == 0 {
+            return Ok(());
+        }
+
+        file.seek_relative(len[0] as i64)?;
+    }
+}
+
+/// Walks the blocks until a second frame is found, or the end of the file.
+fn probe_gif(file: &mut BufReader<impl Read + Seek>) -> ProbeResult {
+    let mut header = [0u8; 13];
+    file.read_exact(&mut header)?;
+
+    let width = u16::from_le_bytes([header[6], header[7]]) as u32;
+    let height = u16::from_le_bytes([header[8], header[9]]) as u32;
+
+    let color_table_len = |flags: u8| {
+        if flags & 0x80 != 0 {
+            3 << ((flags & 0x07) + 1)
+        } else {
+            0
+        }
+    };
+
+    file.seek_relative(color_table_len(header[10]))?;
+
+    let mut frames = 0;
+    let mut transparency = false;
+
+    while frames < 2 {
+        let mut introducer = [0u8; 1];
+
+        // some encoders leave out the trailer
+        if file.read(&mut introducer)? == 0 {
+            break;
+        }
+
+        match introducer[0] {
+            // extension
+            0x21 => {
+                let mut label = [0u8; 1];
+                file.read_exact(&mut label)?;
+
+                if label[0] == 0xF9 {
+                    let mut gce = [0u8; 6];
+                    file.read_exact(&mut gce)?;
+
+                    transparency |= gce[1] & 0x01 != 0;
+
+                    if gce[5] != 0 {
+                        skip_sub_blocks(file)?;
+                    }
+                } else {
+                    skip_sub_blocks(file)?;
+                }
+            }
+            // image descriptor
+            0x2C => {
+                let mut descriptor = [0u8; 9];
+                file.read_exact(&mut descriptor)?;
+
+                file.seek_relative(color_table_len(descriptor[8]) + 1)?; // and the LZW code size
+                skip_sub_blocks(file)?;
+
+                frames += 1;
+            }
+            0x3B => break,
+            other => return Err(format!("invalid GIF block 0x{other:02x}").into()),
+        }
+    }
+
+    // also where the file was cut short before the first frame, which reads like a missing trailer
+    if frames == 0 {
+        return Err("no image in GIF".into());
+    }
+
+    Ok(ImageInfo {
+        width,
+        height,
+        bit_depth: Some(8),
+        channels: Some(if transparency { 4 } else { 3 }),
+        has_alpha: Some(transparency),
+        animated: Some(frames > 1),
+        color: Some(ColorKind::Palette),
+    })
+}
+
+/// Walks the markers up to the first start of frame.
+fn probe_jpeg(file: &mut BufReader<impl Read + Seek>) -> ProbeResult {
+    let mut soi = [0u8; 2];
+    file.read_exact(&mut soi)?;
+
+    if soi != [0xFF, 0xD8] {
+        return Err("missing JPEG start of image".into());
+    }
+
+    loop {
+        let mut marker = [0u8; 2];
+        file.read_exact(&mut marker)?;
+
+        if marker[0] != 0xFF {
+            return Err("invalid JPEG marker".into());
+        }
+
+        match marker[1] {
+            // padding
+            0xFF => {
+                file.seek_relative(-1)?;
+                continue;
+            }
+            // markers without a length
+            0x01 | 0xD0..=0xD7 => continue,
+            _ => {}
+        }
+
+        let mut len = [0u8; 2];
+        file.read_exact(&mut len)?;
+
+        let len = u16::from_be_bytes(len) as i64;
+
+        // SOFn, except DHT, JPG and DAC which share the range
+        if matches!(marker[1], 0xC0..=0xCF) && !matches!(marker[1], 0xC4 | 0xC8 | 0xCC) {
+            let mut sof = [0u8; 6];
+            file.read_exact(&mut sof)?;
+
+            let channels = sof[5] as u32;
+
+            return Ok(ImageInfo {
+                width: u16::from_be_bytes([sof[3], sof[4]]) as u32,
+                height: u16::from_be_bytes([sof[1], sof[2]]) as u32,
+                bit_depth: Some(sof[0] as u32),
+                channels: Some(channels),
+                has_alpha: Some(false),
+                animated: Some(false),
+                color: Some(color_kind(channels, false)),
+            });
+        }
+
+        file.seek_relative(len - 2)?;
+    }
+}
+
+/// Reads the next whitespace-separated token of a Netpbm header, skipping comments.
+fn netpbm_token(file: &mut BufReader<impl Read + Seek>) -> Result<String, Box<dyn std::error::Error>> {
+    let mut token = String::new();
+    let mut byte = [0u8; 1];
+
+    loop {
+        if file.read(&mut byte)? == 0 {
+            break;
+        }
+
+        match byte[0] {
+            b'#' => {
+                file.skip_until(b'\n')?;
+
+                if !token.is_empty() {
+                    break;
+                }
+            }
+            b if b.is_ascii_whitespace() => {
+                if !token.is_empty() {
+                    break;
+                }
+            }
+            b => token.push(b as char),
+        }
+
+        if token.len() > 64 {
+            return Err("invalid Netpbm header".into());
+        }
+    }
+
+    Ok(token)
+}
+
+fn netpbm_number(file: &mut BufReader<impl Read + Seek>) -> Result<u32, Box<dyn std::error::Error>> {
+    Ok(netpbm_token(file)?.parse()?)
+}
+
+/// Handles PBM, PGM, PPM, PAM and PFM files.
+fn probe_netpbm(file: &mut BufReader<impl Read + Seek>) -> ProbeResult {
+    let magic = netpbm_token(file)?;
+
+    // bits needed to hold the maximum value
+    let depth = |maxval: u32| u32::BITS - maxval.leading_zeros();
+
+    let (width, height, bit_depth, channels, has_alpha) = match magic.as_str() {
+        "P1" | "P4" => (netpbm_number(file)?, netpbm_number(file)?, 1, 1, false),
+        "P2" | "P5" => (netpbm_number(file)?, netpbm_number(file)?, 0, 1, false),
+        "P3" | "P6" => (netpbm_number(file)?, netpbm_number(file)?, 0, 3, false),
+        "PF" => (netpbm_number(file)?, netpbm_number(file)?, 32, 3, false),
+        "Pf" => (netpbm_number(file)?, netpbm_number(file)?, 32, 1, false),
+        "P7" => {
+            let (mut width, mut height, mut channels, mut maxval, mut alpha) = (0, 0, 0, 0, false);
+
+            loop {
+                match netpbm_token(file)?.as_str() {
+                    "WIDTH" => width = netpbm_number(file)?,
+                    "HEIGHT" => height = netpbm_number(file)?,
+                    "DEPTH" => channels = netpbm_number(file)?,
+                    "MAXVAL" => maxval = netpbm_number(file)?,
+                    "TUPLTYPE" => alpha = netpbm_token(file)?.ends_with("_ALPHA"),
+                    "ENDHDR" => break,
+                    "" => return Err("unterminated PAM header".into()),
+                    _ => {}
+                }
+            }
+
+            (width, height, depth(maxval), channels, alpha)
+        }
+        _ => return Err("invalid Netpbm magic number".into()),
+    };
+
+    // the maximum value follows the dimensions for the graymap and pixmap formats
+    let bit_depth = match bit_depth {
+        0 => depth(netpbm_number(file)?),
+        d => d,
+    };
+
+    Ok(ImageInfo {
+        width,
+        height,
+        bit_depth: Some(bit_depth),
+        channels: Some(channels),
+        has_alpha: Some(has_alpha),
+        animated: Some(false),
+        color: Some(color_kind(channels, has_alpha)),
+    })
+}
+
+/// PGX headers look like "PG ML +8 640 480", with the sign and depth possibly separated.
+fn probe_pgx(file: &mut BufReader<impl Read + Seek>) -> ProbeResult {
+    let mut line = Vec::new();
+    file.take(256).read_until(b'\n', &mut line)?;
+
+    let line = String::from_utf8(line)?;
+    let mut tokens = line.split_ascii_whitespace().skip(2).filter(|t| *t != "+" && *t != "-");
+
+    let mut next = || tokens.next().ok_or("truncated PGX header");
+
+    let depth: u32 = next()?.trim_start_matches(['+', '-']).parse()?;
+    let width = next()?.parse()?;
+    let height = next()?.parse()?;
+
+    Ok(ImageInfo {
+        width,
+        height,
+        bit_depth: Some(depth),
+        channels: Some(1),
+        has_alpha: Some(false),
+        animated: Some(false),
+        color: Some(ColorKind::Gray),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use super::*;
+
+    fn reader(data: &[u8]) -> BufReader<Cursor<Vec<u8>>> {
+        BufReader::new(Cursor::new(data.to_vec()))
+    }
+
+    fn png_chunk(data: &mut Vec<u8>, kind: &[u8; 4], body: &[u8]) {
+        data.extend_from_slice(&(body.len() as u32).to_be_bytes());
+        data.extend_from_slice(kind);
+        data.extend_from_slice(body);
+        data.extend_from_slice(&[0; 4]); // crc, not checked
+    }
+
+    /// A PNG with the given IHDR fields, followed by the given chunks and an IDAT.
+    fn png(width: u32, height: u32, depth: u8, color_type: u8, chunks: &[(&[u8; 4], &[u8])]) -> Vec<u8> {
+        let mut data = b"\x89PNG\x0d\x0a\x1a\x0a".to_vec();
+
+        let mut ihdr = Vec::new();
+        ihdr.extend_from_slice(&width.to_be_bytes());
+        ihdr.extend_from_slice(&height.to_be_bytes());
+        ihdr.extend_from_slice(&[depth, color_type, 0, 0, 0]);
+        png_chunk(&mut data, b"IHDR", &ihdr);
+
+        for (kind, body) in chunks {
+            png_chunk(&mut data, kind, body);
+        }
+
+        png_chunk(&mut data, b"IDAT", &[0; 16]);
+        data
+    }
+
+    /// A GIF with a global color table and the given blocks, without a trailer.
+    fn gif(width: u16, height: u16, blocks: &[&[u8]]) -> Vec<u8> {
+        let mut data = b"GIF89a".to_vec();
+        data.extend_from_slice(&width.to_le_bytes());
+        data.extend_from_slice(&height.to_le_bytes());
+        data.extend_from_slice(&[0x80, 0, 0]); // 2 color global table
+        data.extend_from_slice(&[0; 6]);
+
+        for block in blocks {
+            data.extend_from_slice(block);
+        }
+
+        data
+    }
+
+    /// An image descriptor without a local color table, and a single data sub-block.
+    const FRAME: &[u8] = &[0x2C, 0, 0, 0, 0, 1, 0, 1, 0, 0, 2, 2, 0x44, 0x01, 0];
+
+    /// A graphic control extension with the transparency flag set.
+    const TRANSPARENT: &[u8] = &[0x21, 0xF9, 4, 0x01, 0, 0, 0, 0];
+
+    #[test]
+    fn png_header() {
+        let info = probe_png(&mut reader(&png(640, 480, 16, 2, &[(b"gAMA", &[0; 4])]))).unwrap();
+
+        assert_eq!((info.width, info.height), (640, 480));
+        assert_eq!(info.bit_depth, Some(16));
+        assert_eq!(info.channels, Some(3));
+        assert_eq!(info.has_alpha, Some(false));
+        assert_eq!(info.animated, Some(false));
+        assert_eq!(info.color, Some(ColorKind::Rgb));
+    }
+
+    #[test]
+    fn png_transparency_and_animation() {
+        let palette = probe_png(&mut reader(&png(1, 1, 4, 3, &[(b"PLTE", &[0; 6]), (b"tRNS", &[0])]))).unwrap();
+
+        assert_eq!(palette.bit_depth, Some(8));
+        assert_eq!(palette.channels, Some(4));
+        assert_eq!(palette.has_alpha, Some(true));
+        assert_eq!(palette.color, Some(ColorKind::Palette));
+
+        let actl = |frames: u32| {
+            let mut body = frames.to_be_bytes().to_vec();
+            body.extend_from_slice(&[0; 4]);
+            body
+        };
+
+        let animated = probe_png(&mut reader(&png(1, 1, 8, 6, &[(b"acTL", &actl(3))]))).unwrap();
+        assert_eq!(animated.animated, Some(true));
+        assert_eq!(animated.color, Some(ColorKind::Rgba));
+
+        let single = probe_png(&mut reader(&png(1, 1, 8, 6, &[(b"acTL", &actl(1))]))).unwrap();
+        assert_eq!(single.animated, Some(false));
+    }
+
+    #[test]
+    fn png_invalid() {
+        let data = png(1, 1, 8, 2, &[(b"tEXt", b"comment")]);
+
+        // cut short in the signature, the header, another chunk and the IDAT chunk header
+        for len in [4, 20, 40, 56] {
+            assert!(probe_png(&mut reader(&data[..len])).is_err(), "truncated at {len}");
+        }
+
+        let mut no_ihdr = data.clone();
+        no_ihdr[12..16].copy_from_slice(b"IHDX");
+        assert!(probe_png(&mut reader(&no_ihdr)).is_err());
+
+        assert!(probe_png(&mut reader(&png(1, 1, 8, 5, &[]))).is_err_and(|e| e.to_string().contains("color type")));
+    }
+
+    #[test]
+    fn gif_frames() {
+        let still = probe_gif(&mut reader(&gif(320, 200, &[FRAME, b";"]))).unwrap();
+
+        assert_eq!((still.width, still.height), (320, 200));
+        assert_eq!(still.animated, Some(false));
+        assert_eq!(still.has_alpha, Some(false));
+        assert_eq!(still.color, Some(ColorKind::Palette));
+
+        // the trailer may be missing
+        let animated = probe_gif(&mut reader(&gif(1, 1, &[TRANSPARENT, FRAME, FRAME]))).unwrap();
+
+        assert_eq!(animated.animated, Some(true));
+        assert_eq!(animated.has_alpha, Some(true));
+        assert_eq!(animated.channels, Some(4));
+    }
+
+    #[test]
+    fn gif_invalid() {
+        assert!(probe_gif(&mut reader(&gif(1, 1, &[b";"]))).is_err());
+
+        let data = gif(1, 1, &[TRANSPARENT, FRAME]);
+
+        // cut short in the header, the color table, the extension and the frame data
+        for len in [8, 15, 22, data.len() - 1] {
+            assert!(probe_gif(&mut reader(&data[..len])).is_err(), "truncated at {len}");
+        }
+
+        assert!(probe_gif(&mut reader(&gif(1, 1, &[&[0x99]]))).is_err_and(|e| e.to_string().contains("0x99")));
+    }
+
+    #[test]
+    fn other_formats() {
+        let jpeg = [
+            &[0xFF, 0xD8, 0xFF, 0xE0, 0, 4, 0, 0][..],
+            &[0xFF, 0xC0, 0, 11, 8, 0, 100, 0, 200, 3],
+        ]
+        .concat();
+
+        let info = probe_jpeg(&mut reader(&jpeg)).unwrap();
+        assert_eq!((info.width, info.height, info.channels), (200, 100, Some(3)));
+        assert!(probe_jpeg(&mut reader(&jpeg[..jpeg.len() - 3])).is_err());
+
+        let info = probe_netpbm(&mut reader(b"P6\n# comment\n3 2\n65535\n")).unwrap();
+        assert_eq!((info.width, info.height, info.bit_depth), (3, 2, Some(16)));
+        assert!(probe_netpbm(&mut reader(b"P6\n3")).is_err());
+
+        let pam = b"P7\nWIDTH 4\nHEIGHT 5\nDEPTH 4\nMAXVAL 255\nTUPLTYPE RGB_ALPHA\nENDHDR\n";
+        let info = probe_netpbm(&mut reader(pam)).unwrap();
+        assert_eq!((info.width, info.height, info.color), (4, 5, Some(ColorKind::Rgba)));
+        assert!(probe_netpbm(&mut reader(&pam[..20])).is_err());
+
+        let info = probe_pgx(&mut reader(b"PG ML + 12 7 9\n")).unwrap();
+        assert_eq!((info.width, info.height, info.bit_depth), (7, 9, Some(12)));
+        assert!(probe_pgx(&mut reader(b"PG ML +8 7")).is_err());
+    }
+}
@@ -1,7 +1,11 @@
-use std::{cmp::Reverse, fmt::Write as _, sync::atomic::Ordering};
+use std::{cmp::Reverse, collections::BTreeMap, fmt::Write as _, sync::atomic::Ordering};
 
 use crate::{
-    app::{ConversionOutcome, FileTab},
+    app::{
+        App2, ConversionOutcome, FileTab, ScanningUIState,
+        scan::{DeviceSummary, ScanError, ScanObserver},
+    },
+    cli::Conv2JxlArgs,
     formatting::{Bytes, DecimalTime, Speed, TimeBreakdown},
 };
 
@@ -11,18 +15,129 @@ impl super::App {
     pub fn draw(&mut self, frame: &mut Frame) {
         self.ui_state.time = self.shared.start.elapsed().as_millis() as u64;
 
+        if let Some(line) = self
+            .shared
+            .next_summary(&mut self.ui_state.summarized, self.ui_state.time)
+        {
+            if self.ui_state.history.len() == super::HISTORY_LEN {
+                self.ui_state.history.pop_front();
+            }
+
+            self.ui_state.history.push_back(line);
+        }
+
         self.render(frame.area(), frame.buffer_mut());
     }
 }
 
+impl super::App2 {
+    pub fn draw(&mut self, frame: &mut Frame) {
+        match self {
+            App2::Started(_) => {}
+            App2::Scanning {
+                args,
+                observer,
+                ui_state,
+                ..
+            } => {
+                ui_state.time = ui_state.start.elapsed().as_millis() as u64;
+
+                render_scanning(args, observer, ui_state, frame.area(), frame.buffer_mut());
+            }
+            App2::Converting(app) => app.draw(frame),
+        }
+    }
+}
+
+fn render_scanning(
+    args: &Conv2JxlArgs,
+    observer: &ScanObserver,
+    ui_state: &ScanningUIState,
+    area: Rect,
+    buf: &mut Buffer,
+) {
+    let layout = Layout::vertical([Constraint::Length(1), Constraint::Length(5), Constraint::Min(0)])
+        .flex(layout::Flex::Legacy)
+        .split(area);
+
+    let throbber = THROBBER[(ui_state.time / 400) as usize % THROBBER.len()];
+
+    Line::raw(format!("{throbber} Scanning {} path(s)...", args.paths.len()))
+        .fg(Color::Green)
+        .render(layout[0], buf);
+
+    let mut found = 0;
+    let mut bytes = 0;
+
+    for (_ft, f) in observer.files.iter() {
+        found += f.found.load(Ordering::Relaxed);
+        bytes += f.bytes.load(Ordering::Relaxed);
+    }
+
+    let stats_text = Text::raw(format!(
+        "Directories: {}/{} read | Files: {found} ({})\n\
+        Excluded: {} | Too recent: {} | Hardlinks: {} | Mismatched: {} | Errors: {}\n\
+        Elapsed: {}",
+        observer.dir_read.load(Ordering::Relaxed),
+        observer.dir_found.load(Ordering::Relaxed),
+        Bytes(bytes),
+        // ---
+        observer.excluded.load(Ordering::Relaxed),
+        observer.recent.load(Ordering::Relaxed),
+        observer.linked.load(Ordering::Relaxed),
+        observer.mismatched.load(Ordering::Relaxed),
+        observer.errors.load(Ordering::Relaxed),
+        // ---
+        TimeBreakdown(ui_state.time as f64),
+    ))
+    .fg(Color::Cyan);
+
+    Paragraph::new(stats_text)
+        .block(Block::new().borders(Borders::all()).title_top("Scanning"))
+        .render(layout[1], buf);
+
+    let types = observer.files.iter().filter_map(|(ft, f)| {
+        let found = f.found.load(Ordering::Relaxed);
+
+        if found == 0 {
+            return None;
+        }
+
+        Some(ListItem::new(format!(
+            "'{ft}': {found} files, {}",
+            Bytes(f.bytes.load(Ordering::Relaxed))
+        )))
+    });
+
+    let devices = device_items(&observer.devices.lock().unwrap());
+
+    let list = List::new(types.chain(devices)).block(
+        Block::new()
+            .border_style(Style::new().fg(Color::Blue).bg(Color::Blue))
+            .border_set(symbols::border::FULL)
+            .title_bottom(Line::raw("Q - Quit").right_aligned().fg(Color::White).bg(Color::Blue))
+            .borders(Borders::all()),
+    );
+
+    Widget::render(list, layout[2], buf);
+}
+
 impl Widget for &super::App {
     fn render(self, area: Rect, buf: &mut Buffer)
     where
         Self: Sized,
     {
-        let layout = Layout::vertical([Constraint::Length(1), Constraint::Length(5), Constraint::Min(0)])
-            .flex(layout::Flex::Legacy)
-            .split(area);
+        // the history only takes up space once there is something in it
+        let history = self.ui_state.history.len() as u16;
+
+        let layout = Layout::vertical([
+            Constraint::Length(1),
+            Constraint::Length(5),
+            Constraint::Length(if history > 0 { history + 2 } else { 0 }),
+            Constraint::Min(0),
+        ])
+        .flex(layout::Flex::Legacy)
+        .split(area);
 
         let mut progress = 0.0;
 
@@ -34,16 +149,47 @@ impl Widget for &super::App {
 
         if *self.shared.conv.paused.0.lock().unwrap() {
             guage = guage.label(Span::raw("Paused").fg(Color::Yellow));
+        } else if let Some(error) = self.shared.conv.scan_error.get() {
+            guage = guage.label(Span::raw(format!("Scan failed: {error}")).fg(Color::Red));
+        } else if let Some(error) = self.shared.conv.watch_error.get() {
+            guage = guage.label(Span::raw(format!("Watch failed: {error}")).fg(Color::Red));
+        } else if let Some(error) = self.shared.conv.log_error.get() {
+            guage = guage.label(Span::raw(error.as_str()).fg(Color::Red));
+        } else if let Some(reason) = self.shared.conv.stop_reason.get() {
+            guage = guage.label(Span::raw(format!("Stopped: {reason}")).fg(Color::Yellow));
         }
 
         guage.render(layout[0], buf);
 
-        self.render_file_list(layout[2], buf);
+        if history > 0 {
+            Paragraph::new(Text::from_iter(self.ui_state.history.iter().map(String::as_str)))
+                .block(Block::new().borders(Borders::all()).title_top("History"))
+                .render(layout[2], buf);
+        }
+
+        self.render_file_list(layout[3], buf);
     }
 }
 
 const THROBBER: &[&str] = &["-", "\\", "|", "/"];
 
+/// Lists files found per device, only if there is more than one.
+fn device_items(devices: &BTreeMap<u64, DeviceSummary>) -> Vec<ListItem<'static>> {
+    if devices.len() < 2 {
+        return Vec::new();
+    }
+
+    Vec::from_iter(devices.values().map(|d| {
+        ListItem::new(format!(
+            "Device '{}': {} files, {}",
+            d.root.display(),
+            d.found,
+            Bytes(d.bytes)
+        ))
+        .fg(Color::Cyan)
+    }))
+}
+
 pub struct SymbolSet {
     pub next_symbol: &'static str,
     pub success_symbol: &'static str,
@@ -387,12 +533,28 @@ impl super::App {
             FileTab::Errors | FileTab::Warnings | FileTab::Inefficient => {
                 let non_success = self.shared.conv.non_success.read().unwrap();
 
+                let scan_errors = &self.shared.conv.scan_errors;
+
+                // paths that could not be read while scanning are listed after conversion errors
+                let scan_errors = (0..scan_errors.len()).filter(|_| tab == FileTab::Errors).map(|i| {
+                    let ScanError { path, error } = &scan_errors[i];
+
+                    let mut text = format!("{error_symbol} [scan] '{}' | {error}", path.display());
+
+                    if self.shared.args.no_unicode {
+                        text = crate::formatting::strip_non_ascii(text, None);
+                    }
+
+                    ListItem::new(text)
+                });
+
                 List::new(
                     non_success
                         .iter()
                         .rev()
                         .copied()
                         .filter_map(|(_, i)| list_files(i))
+                        .chain(scan_errors)
                         .skip(offset)
                         .take(rect.height as usize),
                 )
@@ -401,6 +563,14 @@ impl super::App {
             FileTab::Breakdown => {
                 // This tab shows a breakdown of files by type, with counts and total sizes.
 
+                let provisional = if self.shared.conv.scan_complete.load(Ordering::Relaxed) {
+                    ""
+                } else {
+                    "+"
+                };
+
+                let devices = device_items(&self.shared.conv.devices.lock().unwrap());
+
                 List::new(self.shared.conv.progress.iter().filter_map(|(ft, progress)| {
                     let processed = progress.processed.load(Ordering::Relaxed);
                     let errored = progress.errored.load(Ordering::Relaxed);
@@ -419,8 +589,8 @@ impl super::App {
                     let compression_ratio = if input > 0 { output as f64 / input as f64 * 100.0 } else { 0.0 };
 
                     Some(ListItem::new(Text::raw(format!(
-                        "'{ft}': {count}/{} files ({:.2}% of {}), {} in -> {} out ({:.2}%), {} saved | {} success, {} errors, {} inefficient",
-                        progress.total,
+                        "'{ft}': {count}/{}{provisional} files ({:.2}% of {}), {} in -> {} out ({:.2}%), {} saved | {} success, {} errors, {} inefficient",
+                        progress.total.load(Ordering::Relaxed),
                         (input as f64 / bytes as f64) * 100.0,
                         Bytes(bytes),
                         Bytes(input),
@@ -431,7 +601,7 @@ impl super::App {
                         errored,
                         inefficient
                     ))))
-                }))
+                }).chain(devices))
             }
         };
 
@@ -457,100 +627,70 @@ impl super::App {
     }
 
     fn stats(&self, progress: &mut f64) -> impl Widget {
-        let total_files = self.shared.conv.files.len();
-
-        let mut processed = 0;
-        let mut errored = 0;
-        let mut inefficient = 0;
-        let mut total_bytes = 0;
-        let mut input_bytes = 0;
-        let mut output_bytes = 0;
-        let mut elapsed = 0;
-
-        let real_elapsed = self.shared.start.elapsed().as_millis() as f64;
+        let summary = self.shared.summary(self.ui_state.time);
+        *progress = summary.progress;
 
-        let mut estimated_eta = 0f64;
-        let mut estimated_savings = 0;
+        let scan_complete = !summary.provisional;
+        let provisional = if summary.provisional { "+" } else { "" };
 
-        // for each file type, aggregate the stats and estimate the overall ETA and savings
-        for (_ft, progress) in self.shared.conv.progress.iter() {
-            let current_total_bytes = progress.total_bytes.load(Ordering::Acquire);
-
-            if current_total_bytes == 0 {
-                continue;
-            }
-
-            let current_input_bytes = progress.input_bytes.load(Ordering::Relaxed);
-            let current_output_bytes = progress.output_bytes.load(Ordering::Relaxed);
-            let current_elapsed = progress.elapsed.load(Ordering::Relaxed);
-
-            processed += progress.processed.load(Ordering::Relaxed);
-            errored += progress.errored.load(Ordering::Relaxed);
-            inefficient += progress.inefficient.load(Ordering::Relaxed);
-
-            total_bytes += current_total_bytes;
-            input_bytes += current_input_bytes;
-            output_bytes += current_output_bytes;
-            elapsed += current_elapsed;
+        let scan_errors = match self.shared.conv.scan_errors.len() {
+            0 => String::new(),
+            n => format!(" | Unreadable: {n}"),
+        };
 
-            let remaining_bytes = current_total_bytes.saturating_sub(current_input_bytes);
+        let conv = &self.shared.conv;
 
-            if current_elapsed > 0 {
-                let current_speed = current_input_bytes as f64 / current_elapsed as f64;
+        let excluded = match (
+            conv.excluded.load(Ordering::Relaxed),
+            conv.recent.load(Ordering::Relaxed),
+        ) {
+            (0, 0) => String::new(),
+            (excluded, recent) => format!(" | Excluded: {excluded} | Too recent: {recent}"),
+        };
 
-                // add remaining time for this file type to the overall ETA
-                estimated_eta += remaining_bytes as f64 / current_speed;
-            }
+        let stats_text = Text::raw(format!(
+            "Processed: {}/{}{provisional} ({:.02}% of {}{provisional}) | Errored: {} | Inefficient: {}{scan_errors}{excluded}\n\
+            In: {} | Out: {} ({:.02}%) | Saved: {} ({:.02}%)\n\
+            Elapsed: {} | Speed: {} | ETA: {}{provisional} | Estimated Savings: {}{provisional}",
+            summary.done(),
+            summary.total_files,
+            summary.progress * 100.0,
+            Bytes(summary.total_bytes),
+            summary.errored,
+            summary.inefficient,
+            // ---
+            Bytes(summary.input_bytes),
+            Bytes(summary.output_bytes),
+            summary.compression_ratio(),
+            Bytes(summary.input_bytes.saturating_sub(summary.output_bytes)),
+            (100.0 - summary.compression_ratio()),
+            // ---
+            TimeBreakdown(summary.real_elapsed),
+            Speed::new(
+                summary.input_bytes,
+                summary.elapsed as f64 / self.shared.args.parallel as f64
+            ),
+            DecimalTime(summary.estimated_eta / self.shared.args.parallel as f64),
+            Bytes(summary.estimated_savings),
+        ))
+        .fg(Color::Cyan);
 
-            let current_compression_ratio = if current_input_bytes > 0 {
-                current_output_bytes as f64 / current_input_bytes as f64
-            } else {
-                0.0
-            };
+        let mut title = String::from("Statistics");
 
-            // estimate savings for remaining bytes based on current compression ratio
-            estimated_savings += ((1.0 - current_compression_ratio) * remaining_bytes as f64) as u64
-                + (current_input_bytes - current_output_bytes);
+        if !scan_complete {
+            title.push_str(" (still scanning)");
+        } else if self.shared.conv.watching.load(Ordering::Relaxed) {
+            title.push_str(" (watching for changes)");
         }
 
-        for thread in &self.shared.conv.active {
-            let start_time = thread.start_time.load(Ordering::Relaxed);
-            estimated_eta -= self.ui_state.time.saturating_sub(start_time) as f64;
+        if let Some(reason) = self.shared.conv.stop_reason.get() {
+            let _ = write!(title, " | Stopped: {reason}");
         }
 
-        if total_bytes == 0 {
-            *progress = 1.0;
-            estimated_eta = 0.0;
-        } else {
-            *progress = input_bytes as f64 / total_bytes as f64;
+        if let Some(seed) = self.shared.args.seed {
+            let _ = write!(title, " | Seed: {seed}");
         }
 
-        let total_compression_ratio = if input_bytes > 0 {
-            output_bytes as f64 / input_bytes as f64 * 100.0
-        } else {
-            0.0
-        };
-
-        let stats_text = Text::raw(format!(
-            "Processed: {}/{total_files} ({:.02}% of {}) | Errored: {errored} | Inefficient: {inefficient}\n\
-            In: {} | Out: {} ({total_compression_ratio:.02}%) | Saved: {} ({:.02}%)\n\
-            Elapsed: {} | Speed: {} | ETA: {} | Estimated Savings: {}",
-            processed + errored + inefficient,
-            *progress * 100.0,
-            Bytes(total_bytes),
-            // ---
-            Bytes(input_bytes),
-            Bytes(output_bytes),
-            Bytes(input_bytes.saturating_sub(output_bytes)),
-            (100.0 - total_compression_ratio),
-            // ---
-            TimeBreakdown(real_elapsed),
-            Speed::new(input_bytes, elapsed as f64 / self.shared.args.parallel as f64),
-            DecimalTime(estimated_eta / self.shared.args.parallel as f64),
-            Bytes(estimated_savings),
-        ))
-        .fg(Color::Cyan);
-
-        Paragraph::new(stats_text).block(Block::new().borders(Borders::all()).title_top("Statistics"))
+        Paragraph::new(stats_text).block(Block::new().borders(Borders::all()).title_top(title))
     }
 }
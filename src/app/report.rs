@@ -0,0 +1,329 @@
+use std::{
+    collections::BTreeMap,
+    fs::File,
+    io::{BufWriter, Write},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use serde::Serialize;
+
+use super::{journal::JournalOutcome, *};
+use crate::cli::ReportFormat;
+
+/// A file found, and what became of it.
+#[derive(Debug, Serialize)]
+struct FileReport<'a> {
+    path: Cow<'a, str>,
+    #[serde(rename = "type")]
+    ftype: String,
+    /// Not set for files that were never processed, such as after stopping early
+    outcome: Option<JournalOutcome>,
+    /// Error, or warning for a converted file
+    message: Option<&'a str>,
+    input: u64,
+    output: Option<u64>,
+    /// Output size as a fraction of the input size
+    ratio: Option<f64>,
+    /// Time spent converting in milliseconds, over all attempts
+    time: Option<u64>,
+    /// Quality of the last attempt
+    quality: Option<u8>,
+}
+
+#[derive(Debug, Default, Serialize)]
+struct Totals {
+    files: usize,
+    converted: usize,
+    /// Converted files with a warning, also counted as converted
+    warnings: usize,
+    skipped: usize,
+    inefficient: usize,
+    failed: usize,
+    unprocessed: usize,
+    input_bytes: u64,
+    /// Input bytes of the files converted
+    converted_bytes: u64,
+    output_bytes: u64,
+}
+
+impl Totals {
+    fn add(&mut self, file: &FileReport) {
+        self.files += 1;
+        self.input_bytes += file.input;
+
+        match file.outcome {
+            Some(JournalOutcome::Converted) => {
+                self.converted += 1;
+                self.warnings += file.message.is_some() as usize;
+                self.converted_bytes += file.input;
+                self.output_bytes += file.output.unwrap_or(0);
+            }
+            Some(JournalOutcome::Skipped) => self.skipped += 1,
+            Some(JournalOutcome::Inefficient) => self.inefficient += 1,
+            Some(JournalOutcome::Failed) => self.failed += 1,
+            Some(JournalOutcome::Started) | None => self.unprocessed += 1,
+        }
+    }
+}
+
+/// Options in effect for the run.
+#[derive(Debug, Serialize)]
+struct Settings<'a> {
+    quality: u8,
+    quality_if_inefficient: Option<u8>,
+    effort: u8,
+    min_ratio: f32,
+    min_inefficient_size: Option<u64>,
+    lossless_jpeg: bool,
+    jpeg_reconstruction: bool,
+    threads: i32,
+    parallel: i32,
+    extensions: Vec<String>,
+    no_preserve_extension: bool,
+    overwrite: bool,
+    delete: bool,
+    truncate: bool,
+    dry_run: bool,
+    seed: Option<u64>,
+    paths: Vec<Cow<'a, str>>,
+}
+
+#[derive(Debug, Serialize)]
+struct Report<'a> {
+    version: &'static str,
+    cjxl_version: Option<String>,
+    command_line: Vec<String>,
+    /// When the run started, in seconds since the Unix epoch
+    started: u64,
+    /// Time from start to end of the run in milliseconds
+    wall_time: u64,
+    /// Why the run stopped before converting every file, if it did
+    stopped: Option<String>,
+    settings: Settings<'a>,
+    totals: Totals,
+    types: BTreeMap<String, Totals>,
+    /// Left out of the summary written next to a CSV report, which has the files itself
+    #[serde(skip_serializing_if = "Option::is_none")]
+    files: Option<Vec<FileReport<'a>>>,
+}
+
+/// First line of `cjxl --version`, if it runs.
+fn cjxl_version() -> Option<String> {
+    let output = std::process::Command::new("cjxl").arg("--version").output().ok()?;
+
+    String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .map(str::trim)
+        .find(|line| !line.is_empty())
+        .map(str::to_owned)
+}
+
+fn file_report(file: &FileEntry) -> FileReport<'_> {
+    let (outcome, message, sizes) = match file.state.get() {
+        Some(ConversionOutcome::Success(input, output)) => {
+            (Some(JournalOutcome::Converted), None, Some((*input, *output)))
+        }
+        Some(ConversionOutcome::Warning(input, output, warning)) => (
+            Some(JournalOutcome::Converted),
+            Some(warning.as_ref()),
+            Some((*input, *output)),
+        ),
+        Some(ConversionOutcome::Skipped) => (Some(JournalOutcome::Skipped), None, None),
+        Some(ConversionOutcome::Inefficient(input, output)) => {
+            (Some(JournalOutcome::Inefficient), None, Some((*input, *output)))
+        }
+        Some(ConversionOutcome::Error(e)) => (Some(JournalOutcome::Failed), Some(e.as_ref()), None),
+        None => (None, None, None),
+    };
+
+    let input = sizes.map_or(file.metadata.len(), |(input, _)| input);
+    let attempted = file.quality.get().copied();
+
+    FileReport {
+        path: file.path.to_string_lossy(),
+        ftype: file.ext.to_string(),
+        outcome,
+        message,
+        input,
+        output: sizes.map(|(_, output)| output),
+        ratio: sizes.map(|(input, output)| output as f64 / input.max(1) as f64),
+        time: attempted.map(|_| file.elapsed.load(Ordering::Relaxed)),
+        quality: attempted,
+    }
+}
+
+/// Quotes a CSV field if needed.
+fn csv_field(field: &str) -> Cow<'_, str> {
+    match field.contains([',', '"', '\n', '\r']) {
+        true => format!("\"{}\"", field.replace('"', "\"\"")).into(),
+        false => field.into(),
+    }
+}
+
+fn write_csv(out: &mut impl Write, files: &[FileReport]) -> std::io::Result<()> {
+    writeln!(out, "path,type,outcome,message,input,output,ratio,time,quality")?;
+
+    let optional = |value: Option<String>| value.unwrap_or_default();
+
+    for file in files {
+        writeln!(
+            out,
+            "{},{},{},{},{},{},{},{},{}",
+            csv_field(&file.path),
+            file.ftype,
+            file.outcome.map_or("", JournalOutcome::name),
+            csv_field(file.message.unwrap_or_default()),
+            file.input,
+            optional(file.output.map(|output| output.to_string())),
+            optional(file.ratio.map(|ratio| format!("{ratio:.4}"))),
+            optional(file.time.map(|time| time.to_string())),
+            optional(file.quality.map(|quality| quality.to_string())),
+        )?;
+    }
+
+    Ok(())
+}
+
+/// Path of the JSON summary written next to a CSV report, with everything but the files:
+/// `report.csv` gets `report.summary.json`.
+pub fn summary_path(path: &Path) -> PathBuf {
+    path.with_extension("summary.json")
+}
+
+/// Writes a report of every file found and its outcome to `path`, for --report.
+/// Without a format given, a path ending in .csv gets CSV, and anything else JSON.
+/// A CSV report only has a row per file, so the rest goes in a JSON summary at [`summary_path`].
+pub fn write(
+    shared: &SharedState,
+    path: &Path,
+    format: Option<ReportFormat>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let format = format.unwrap_or(match path.extension() {
+        Some(ext) if ext.eq_ignore_ascii_case("csv") => ReportFormat::Csv,
+        _ => ReportFormat::Json,
+    });
+
+    let conv = &shared.conv;
+    let files = Vec::from_iter((0..conv.files.len()).map(|i| file_report(&conv.files[i])));
+
+    let (mut totals, mut types) = (Totals::default(), BTreeMap::<String, Totals>::new());
+
+    for file in &files {
+        totals.add(file);
+        types.entry(file.ftype.clone()).or_default().add(file);
+    }
+
+    let args = &shared.args;
+    let wall_time = shared.start.elapsed();
+
+    let report = Report {
+        version: env!("CARGO_PKG_VERSION"),
+        cjxl_version: cjxl_version(),
+        command_line: std::env::args().collect(),
+        started: (SystemTime::now() - wall_time)
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |time| time.as_secs()),
+        wall_time: wall_time.as_millis() as u64,
+        stopped: conv.stop_reason.get().map(|reason| reason.to_string()),
+        settings: Settings {
+            quality: args.quality,
+            quality_if_inefficient: args.quality_if_inefficient,
+            effort: args.effort,
+            min_ratio: args.min_ratio,
+            min_inefficient_size: args.min_inefficient_size,
+            lossless_jpeg: args.lossless_jpeg,
+            jpeg_reconstruction: !args.disable_jpeg_reconstruction,
+            threads: args.threads,
+            parallel: args.parallel,
+            extensions: {
+                let mut extensions = Vec::from_iter(args.extensions.iter().map(|ftype| ftype.to_string()));
+                extensions.sort();
+                extensions
+            },
+            no_preserve_extension: args.no_preserve_extension,
+            overwrite: args.overwrite,
+            delete: args.delete,
+            truncate: args.truncate,
+            dry_run: args.dry_run,
+            seed: args.seed,
+            paths: Vec::from_iter(args.paths.iter().map(|path| path.to_string_lossy())),
+        },
+        totals,
+        types,
+        files: None,
+    };
+
+    let mut out = BufWriter::new(File::create(path)?);
+
+    let report = match format {
+        ReportFormat::Json => Report {
+            files: Some(files),
+            ..report
+        },
+        ReportFormat::Csv => {
+            write_csv(&mut out, &files)?;
+            out.flush()?;
+
+            out = BufWriter::new(File::create(summary_path(path))?);
+            report
+        }
+    };
+
+    serde_json::to_writer_pretty(&mut out, &report)?;
+    writeln!(out)?;
+    out.flush()?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn csv_rows() {
+        let files = [
+            FileReport {
+                path: "a, \"b\".png".into(),
+                ftype: "png".into(),
+                outcome: Some(JournalOutcome::Converted),
+                message: None,
+                input: 200,
+                output: Some(50),
+                ratio: Some(0.25),
+                time: Some(12),
+                quality: Some(90),
+            },
+            FileReport {
+                path: "c.jpeg".into(),
+                ftype: "jpeg".into(),
+                outcome: None,
+                message: None,
+                input: 100,
+                output: None,
+                ratio: None,
+                time: None,
+                quality: None,
+            },
+        ];
+
+        let mut out = Vec::new();
+        write_csv(&mut out, &files).unwrap();
+
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "path,type,outcome,message,input,output,ratio,time,quality\n\
+             \"a, \"\"b\"\".png\",png,converted,,200,50,0.2500,12,90\n\
+             c.jpeg,jpeg,,,100,,,,\n"
+        );
+    }
+
+    #[test]
+    fn summary_next_to_csv() {
+        assert_eq!(
+            summary_path(Path::new("out/report.csv")),
+            Path::new("out/report.summary.json")
+        );
+        assert_eq!(summary_path(Path::new("report")), Path::new("report.summary.json"));
+    }
+}
@@ -0,0 +1,323 @@
+use std::{
+    fs::File,
+    io::{BufReader, ErrorKind, Read, Seek},
+};
+
+use super::*;
+use crate::cli::{DetectMethod, FileTypes, RestoreArgs};
+
+/// What became of a JPEG XL file.
+enum Restored {
+    /// Decoded back to the original at this path
+    Restored(PathBuf),
+    /// Left alone, for this reason
+    Skipped(String),
+}
+
+/// Signature of a JPEG XL file in a container, rather than a bare codestream.
+const CONTAINER: &[u8; 12] = b"\0\0\0\x0cJXL \r\n\x87\n";
+
+/// Returns true if the JPEG XL file has a `jbrd` box, with the data to reconstruct the original JPEG bit-exactly.
+/// Without it `djxl` would encode a new JPEG instead. A bare codestream has no boxes, so never has one.
+fn has_jpeg_reconstruction(file: &mut BufReader<impl Read + Seek>) -> std::io::Result<bool> {
+    let mut signature = [0u8; 12];
+
+    match file.read_exact(&mut signature) {
+        Err(e) if e.kind() == ErrorKind::UnexpectedEof => return Ok(false),
+        result => result?,
+    }
+
+    if signature != *CONTAINER {
+        return Ok(false);
+    }
+
+    loop {
+        let mut header = [0u8; 8];
+
+        match file.read_exact(&mut header) {
+            Err(e) if e.kind() == ErrorKind::UnexpectedEof => return Ok(false),
+            result => result?,
+        }
+
+        if &header[4..] == b"jbrd" {
+            return Ok(true);
+        }
+
+        let len = match u32::from_be_bytes([header[0], header[1], header[2], header[3]]) {
+            // the last box, up to the end of the file
+            0 => return Ok(false),
+            // the size follows as 64 bits
+            1 => {
+                let mut size = [0u8; 8];
+                file.read_exact(&mut size)?;
+
+                u64::from_be_bytes(size).checked_sub(16)
+            }
+            size => (size as u64).checked_sub(8),
+        };
+
+        let len = len
+            .and_then(|len| i64::try_from(len).ok())
+            .ok_or_else(|| std::io::Error::new(ErrorKind::InvalidData, "invalid JPEG XL box size"))?;
+
+        file.seek_relative(len)?;
+    }
+}
+
+/// Finds the original path and type of a converted file, from the extension in ".<ext>.jxl",
+/// or for a plain ".jxl" from the empty file --truncate left next to it.
+///
+/// Outputs are named after the type rather than the extension the original had, as with `photo.jpeg.jxl`
+/// for `photo.jpg`, so a file under any of the extensions of the type is taken as the original.
+fn original(args: &Conv2JxlArgs, jxl: &Path) -> Option<(PathBuf, FileType)> {
+    // nested archives are converted in place, and JPEG XL is never converted again
+    let restorable = |ftype: &FileType| !matches!(ftype, FileType::JXL | FileType::ZIP);
+
+    let named = Path::new(jxl.file_stem()?)
+        .extension()
+        .and_then(|ext| ext.to_str())
+        .and_then(|ext| args.ext_map.get(ext))
+        .filter(restorable);
+
+    if let Some(ftype) = named {
+        let path = jxl.with_extension("");
+
+        let existing = args
+            .ext_map
+            .0
+            .iter()
+            .filter(|&&(_, other)| other == ftype)
+            .map(|(ext, _)| path.with_extension(ext))
+            .find(|original| std::fs::metadata(original).is_ok_and(|metadata| metadata.is_file()));
+
+        return Some((existing.unwrap_or(path), ftype));
+    }
+
+    // converted with --no-preserve-extension, so only the stub tells what the original was
+    args.ext_map
+        .0
+        .iter()
+        .filter(|(_, ftype)| restorable(ftype))
+        .find_map(|(ext, ftype)| {
+            let stub = jxl.with_extension(ext);
+
+            std::fs::metadata(&stub)
+                .is_ok_and(|metadata| metadata.is_file() && metadata.len() == 0)
+                .then_some((stub, *ftype))
+        })
+}
+
+/// Decodes `file` back to its original format next to it with `djxl`, replacing the stub left by --truncate if any.
+fn restore_file(
+    args: &Conv2JxlArgs,
+    restore: &RestoreArgs,
+    file: &FileEntry,
+) -> Result<Restored, Box<dyn std::error::Error>> {
+    let Some((path, ftype)) = original(args, &file.path) else {
+        return Ok(Restored::Skipped("the original format is unknown".to_owned()));
+    };
+
+    // an empty file is a stub left by --truncate, anything else is the original itself
+    let stub = match std::fs::metadata(&path) {
+        Ok(metadata) if metadata.len() > 0 => {
+            return Ok(Restored::Skipped(format!("'{}' is still there", path.display())));
+        }
+        Ok(metadata) => Some(metadata),
+        Err(_) => None,
+    };
+
+    // only bit-exact JPEGs are restored, as the JPEG XL file would be deleted for a lossy copy with --delete
+    if ftype == FileType::JPEG && !has_jpeg_reconstruction(&mut BufReader::new(File::open(&file.path)?))? {
+        return Ok(Restored::Skipped(
+            "no JPEG reconstruction data, so the original can't be restored bit-exactly".to_owned(),
+        ));
+    }
+
+    if args.dry_run {
+        return Ok(Restored::Restored(path));
+    }
+
+    let dir = path.parent().filter(|dir| !dir.as_os_str().is_empty());
+
+    // djxl picks the output format from the extension
+    let output = tempfile::Builder::new()
+        .suffix(&format!(".{ftype}"))
+        .tempfile_in(dir.unwrap_or(Path::new(".")))?;
+
+    let png = match ftype.needs_conversion() {
+        true => Some(tempfile::Builder::new().suffix(".png").tempfile()?),
+        false => None,
+    };
+
+    // JPEGs are reconstructed bit-exactly from the reconstruction data checked for above
+    let result = std::process::Command::new("djxl")
+        .arg(&file.path)
+        .arg(png.as_ref().map_or(output.path(), |png| png.path()))
+        .output()?;
+
+    if !result.status.success() {
+        return Err(format!(
+            "Decoding command failed with {}: {}",
+            result.status,
+            String::from_utf8_lossy(&result.stderr).trim()
+        )
+        .into());
+    }
+
+    if let Some(png) = png {
+        super::conv2png::png2(png.path(), ftype, output.path())?;
+    }
+
+    // the output kept the times of the original, and the stub its permissions
+    let permissions = stub.map_or_else(|| file.metadata.permissions(), |stub| stub.permissions());
+    output.as_file().set_permissions(permissions)?;
+
+    if let Some(times) = super::convert::file_times(&file.metadata) {
+        output.as_file().set_times(times)?;
+    }
+
+    output.persist(&path).map_err(|e| e.error)?;
+
+    if restore.delete
+        && let Err(e) = std::fs::remove_file(&file.path)
+    {
+        return Err(format!(
+            "Restored '{}', but failed to delete the JPEG XL file: {e}",
+            path.display()
+        )
+        .into());
+    }
+
+    Ok(Restored::Restored(path))
+}
+
+/// Finds converted files and decodes them back to their original format, printing a line for each.
+/// Returns false if any failed to restore.
+pub fn run(mut args: Conv2JxlArgs, restore: &RestoreArgs) -> Result<bool, Box<dyn std::error::Error>> {
+    // converted files are what is looked for, whatever the types to convert
+    args.extensions = FileTypes(std::iter::once(FileType::JXL).collect());
+    args.detect = DetectMethod::Extension;
+
+    eprintln!("Scanning...");
+
+    let found = args.scan(&scan::ScanObserver::default())?;
+
+    let next = AtomicUsize::new(0);
+    let (restored, failed) = (AtomicUsize::new(0), AtomicUsize::new(0));
+
+    std::thread::scope(|s| {
+        for _ in 0..args.parallel {
+            s.spawn(|| {
+                loop {
+                    let i = next.fetch_add(1, Ordering::Relaxed);
+
+                    if i >= found.files.len() {
+                        break;
+                    }
+
+                    let file = &found.files[i];
+
+                    match restore_file(&args, restore, file) {
+                        Ok(Restored::Restored(path)) => {
+                            restored.fetch_add(1, Ordering::Relaxed);
+                            println!("restored: {} ({})", path.display(), file.path.display());
+                        }
+                        Ok(Restored::Skipped(reason)) => println!("skipped: {}: {reason}", file.path.display()),
+                        Err(e) => {
+                            failed.fetch_add(1, Ordering::Relaxed);
+                            println!("failed: {}: {e}", file.path.display());
+                        }
+                    }
+                }
+            });
+        }
+    });
+
+    let failed = failed.into_inner();
+
+    eprintln!(
+        "Restored {} of {} files, {failed} failed",
+        restored.into_inner(),
+        found.files.len()
+    );
+
+    Ok(failed == 0)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use super::*;
+
+    fn reconstructable(data: &[u8]) -> std::io::Result<bool> {
+        has_jpeg_reconstruction(&mut BufReader::new(Cursor::new(data)))
+    }
+
+    /// A box of the given type with `len` bytes of content.
+    fn jxl_box(kind: &[u8; 4], len: usize) -> Vec<u8> {
+        let mut data = Vec::from(((8 + len) as u32).to_be_bytes());
+        data.extend_from_slice(kind);
+        data.resize(8 + len, 0);
+        data
+    }
+
+    #[test]
+    fn reconstruction_box() {
+        let container = |boxes: &[Vec<u8>]| [CONTAINER.to_vec(), boxes.concat()].concat();
+
+        // a bare codestream
+        assert!(!reconstructable(b"\xff\x0a\x00\x00").unwrap());
+        assert!(!reconstructable(b"").unwrap());
+
+        assert!(!reconstructable(&container(&[jxl_box(b"ftyp", 12), jxl_box(b"jxlc", 20)])).unwrap());
+        assert!(
+            reconstructable(&container(&[
+                jxl_box(b"ftyp", 12),
+                jxl_box(b"jbrd", 30),
+                jxl_box(b"jxlc", 20)
+            ]))
+            .unwrap()
+        );
+
+        // a 64-bit size
+        let mut large = Vec::from(1u32.to_be_bytes());
+        large.extend_from_slice(b"Exif");
+        large.extend_from_slice(&(16u64 + 4).to_be_bytes());
+        large.extend_from_slice(&[0; 4]);
+
+        assert!(reconstructable(&container(&[large, jxl_box(b"jbrd", 4)])).unwrap());
+
+        // a box up to the end of the file is the last one
+        let mut last = jxl_box(b"jxlc", 4);
+        last[..4].copy_from_slice(&[0; 4]);
+
+        assert!(!reconstructable(&container(&[last])).unwrap());
+
+        // smaller than its own header
+        assert!(reconstructable(&container(&[Vec::from(*b"\0\0\0\x04jxlc")])).is_err());
+    }
+
+    #[test]
+    fn original_under_another_extension() {
+        let dir = tempfile::tempdir().unwrap();
+        let jxl = dir.path().join("photo.jpeg.jxl");
+
+        let mut args: Conv2JxlArgs = argh::FromArgs::from_args(&["conv2jxl"], &["--truncate"]).unwrap();
+        args.normalize();
+
+        // deleted, so restored under the name of the type
+        assert_eq!(
+            original(&args, &jxl),
+            Some((dir.path().join("photo.jpeg"), FileType::JPEG))
+        );
+
+        // the stub --truncate left
+        File::create(dir.path().join("photo.jpg")).unwrap();
+
+        assert_eq!(
+            original(&args, &jxl),
+            Some((dir.path().join("photo.jpg"), FileType::JPEG))
+        );
+    }
+}
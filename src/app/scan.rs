@@ -1,8 +1,20 @@
-use std::{ffi::OsStr, str::FromStr as _};
+use std::{collections::HashMap, ffi::OsStr, path::Path};
 
-use crate::cli::{Conv2JxlArgs, SortMethod, SortOrder};
+use crossbeam_queue::SegQueue;
+use ignore::{
+    Match,
+    gitignore::{Gitignore, GitignoreBuilder},
+};
+
+use super::{
+    expr::{Expr, Properties},
+    filter::PathFilter,
+    probe::ImageInfo,
+};
+use crate::cli::{Conv2JxlArgs, DetectMethod, HardlinkPolicy, HumanDuration, SortMethod, SortOrder, TimeField};
 
 use super::*;
+use crate::utils::natural_cmp;
 
 #[derive(Debug, Default)]
 pub struct FileScanObserver {
@@ -14,7 +26,809 @@ pub struct FileScanObserver {
 pub struct ScanObserver {
     pub dir_read: AtomicU64,
     pub dir_found: AtomicU64,
+    /// Files and directories excluded by --include/--exclude, ignore files or --one-file-system,
+    /// and files outside --newer-than/--older-than, the dimension limits or --where
+    pub excluded: AtomicU64,
+    /// Files skipped by --ignore-recent
+    pub recent: AtomicU64,
+    /// Files whose extension does not match their detected content
+    pub mismatched: AtomicU64,
+    /// Paths that could not be read
+    pub errors: AtomicU64,
+    /// Files not converted on their own because they are another hardlink to a file already found,
+    /// or skipped by --hardlinks skip
+    pub linked: AtomicU64,
     pub files: PerFileType<FileScanObserver>,
+    /// Files found per device, keyed by device number
+    pub devices: Mutex<BTreeMap<u64, DeviceSummary>>,
+}
+
+/// Files found on a single device (filesystem).
+#[derive(Debug, Default, Clone)]
+pub struct DeviceSummary {
+    /// The topmost directory scanned on the device, to tell devices apart
+    pub root: PathBuf,
+    pub found: u64,
+    pub bytes: u64,
+}
+
+impl DeviceSummary {
+    /// Records that `dir` is on this device, keeping the topmost directory seen as its name.
+    fn saw_dir(&mut self, dir: &Path) {
+        if self.root.as_os_str().is_empty() || dir.components().count() < self.root.components().count() {
+            self.root = dir.to_owned();
+        }
+    }
+
+    fn merge(&mut self, other: &DeviceSummary) {
+        if !other.root.as_os_str().is_empty() {
+            self.saw_dir(&other.root);
+        }
+
+        self.found += other.found;
+        self.bytes += other.bytes;
+    }
+}
+
+/// Files and per-device tallies collected by a single scanning thread,
+/// so the shared device summaries are only locked once per directory rather than for every file.
+#[derive(Default)]
+struct Collected {
+    /// Files found, unless streaming
+    files: Vec<FileEntry>,
+    devices: BTreeMap<u64, DeviceSummary>,
+}
+
+/// A path that could not be read while scanning.
+#[derive(Debug)]
+pub struct ScanError {
+    pub path: PathBuf,
+    pub error: std::io::Error,
+}
+
+/// Name of the ignore files, using gitignore syntax, that are always honored while scanning.
+pub const IGNORE_FILE: &str = ".conv2jxlignore";
+
+/// Device and inode number, which identify a file or directory no matter which path leads to it.
+type Inode = (u64, u64);
+
+#[cfg(unix)]
+fn device(metadata: &std::fs::Metadata) -> Option<u64> {
+    use std::os::unix::fs::MetadataExt;
+
+    Some(metadata.dev())
+}
+
+#[cfg(unix)]
+fn inode(metadata: &std::fs::Metadata) -> Option<Inode> {
+    use std::os::unix::fs::MetadataExt;
+
+    Some((metadata.dev(), metadata.ino()))
+}
+
+#[cfg(unix)]
+fn link_count(metadata: &std::fs::Metadata) -> u64 {
+    use std::os::unix::fs::MetadataExt;
+
+    metadata.nlink()
+}
+
+/// Status change time, updated along with the modification time but also by renames and permission changes.
+#[cfg(unix)]
+fn changed(metadata: &std::fs::Metadata) -> std::io::Result<std::time::SystemTime> {
+    use std::{
+        os::unix::fs::MetadataExt,
+        time::{Duration, UNIX_EPOCH},
+    };
+
+    let nanos = Duration::from_nanos(metadata.ctime_nsec() as u64);
+
+    Ok(match u64::try_from(metadata.ctime()) {
+        Ok(secs) => UNIX_EPOCH + Duration::from_secs(secs) + nanos,
+        Err(_) => UNIX_EPOCH - Duration::from_secs(metadata.ctime().unsigned_abs()) + nanos,
+    })
+}
+
+// the Windows equivalents are still unstable
+#[cfg(not(unix))]
+fn device(_metadata: &std::fs::Metadata) -> Option<u64> {
+    None
+}
+
+#[cfg(not(unix))]
+fn inode(_metadata: &std::fs::Metadata) -> Option<Inode> {
+    None
+}
+
+#[cfg(not(unix))]
+fn link_count(_metadata: &std::fs::Metadata) -> u64 {
+    1
+}
+
+#[cfg(not(unix))]
+fn changed(_metadata: &std::fs::Metadata) -> std::io::Result<std::time::SystemTime> {
+    Err(std::io::ErrorKind::Unsupported.into())
+}
+
+/// Returns the given time of a file.
+fn file_time(metadata: &std::fs::Metadata, field: TimeField) -> std::io::Result<std::time::SystemTime> {
+    match field {
+        TimeField::MTime => metadata.modified(),
+        TimeField::CTime => changed(metadata),
+        TimeField::BTime => metadata.created(),
+    }
+}
+
+/// Identity of a directory, to avoid reading it twice through symlinks or loops.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+enum DirId {
+    Inode(Inode),
+    /// Canonical path, where inode numbers are unavailable
+    Path(PathBuf),
+}
+
+/// Rules from the ignore files in a directory and its ancestors, innermost first.
+struct IgnoreRules {
+    matcher: Gitignore,
+    parent: Option<Arc<IgnoreRules>>,
+}
+
+impl IgnoreRules {
+    fn is_ignored(&self, path: &Path, is_dir: bool) -> bool {
+        let mut rules = Some(self);
+
+        // the innermost rules that match decide, like with nested .gitignore files
+        while let Some(r) = rules {
+            match r.matcher.matched(path, is_dir) {
+                Match::Ignore(_) => return true,
+                Match::Whitelist(_) => return false,
+                Match::None => rules = r.parent.as_deref(),
+            }
+        }
+
+        false
+    }
+}
+
+/// A directory left to read, with its depth, device and inherited ignore rules.
+type PendingDir = (u64, PathBuf, Option<u64>, Option<Arc<IgnoreRules>>);
+
+/// Shared state for the directory walking threads.
+struct Walker<'a> {
+    args: &'a Conv2JxlArgs,
+    observer: &'a ScanObserver,
+    patterns: PathFilter,
+    visited: scc::HashSet<DirId, foldhash::fast::FixedState>,
+    /// Files with more than one hardlink that have been found, with the other links found to them
+    links: scc::HashMap<Inode, Vec<PathBuf>, foldhash::fast::FixedState>,
+    pending_dirs: SegQueue<PendingDir>,
+    /// Number of directories either queued or being read, used to tell when the walk is done
+    outstanding: AtomicUsize,
+    /// Errors encountered while walking, unless streaming
+    errors: Mutex<Vec<ScanError>>,
+    /// When streaming, files are pushed here as they are found instead of being collected
+    stream: Option<&'a ConversionState>,
+}
+
+impl<'a> Walker<'a> {
+    fn new(
+        args: &'a Conv2JxlArgs,
+        observer: &'a ScanObserver,
+        stream: Option<&'a ConversionState>,
+    ) -> Result<Self, Box<dyn std::error::Error>> {
+        Ok(Walker {
+            args,
+            observer,
+            patterns: PathFilter::new(args.include.iter().chain(&args.filter), &args.exclude)?,
+            visited: scc::HashSet::with_capacity_and_hasher(1024, foldhash::fast::FixedState::default()),
+            links: scc::HashMap::default(),
+            pending_dirs: SegQueue::new(),
+            outstanding: AtomicUsize::new(0),
+            errors: Mutex::default(),
+            stream,
+        })
+    }
+
+    fn stopped(&self) -> bool {
+        self.stream.is_some_and(|conv| conv.stopping.load(Ordering::Relaxed))
+    }
+
+    /// Records an error for the given path, so the walk can carry on with other entries.
+    fn error(&self, path: &Path, error: std::io::Error) {
+        self.observer.errors.fetch_add(1, Ordering::Relaxed);
+
+        let error = ScanError {
+            path: path.to_owned(),
+            error,
+        };
+
+        match self.stream {
+            Some(conv) => {
+                conv.scan_errors.push(error);
+            }
+            None => self.errors.lock().unwrap().push(error),
+        }
+    }
+
+    fn found(&self, file: FileEntry, collected: &mut Collected) {
+        let f = self.observer.files.get(file.ext);
+
+        f.found.fetch_add(1, Ordering::Relaxed);
+        f.bytes.fetch_add(file.metadata.len(), Ordering::Relaxed);
+
+        if let Some(device) = device(&file.metadata) {
+            let summary = collected.devices.entry(device).or_default();
+
+            if let Some(parent) = file.path.parent() {
+                summary.saw_dir(parent);
+            }
+
+            summary.found += 1;
+            summary.bytes += file.metadata.len();
+        }
+
+        match self.stream {
+            Some(conv) => conv.push(file),
+            None => collected.files.push(file),
+        }
+    }
+
+    /// Checks the file times against --ignore-recent and --newer-than/--older-than.
+    fn accept_times(&self, metadata: &std::fs::Metadata) -> bool {
+        let args = self.args;
+
+        if args.ignore_recent.is_none() && args.newer_than.is_none() && args.older_than.is_none() {
+            return true;
+        }
+
+        let now = std::time::SystemTime::now();
+
+        // age of the given time, with times in the future counting as brand new
+        let age = |time: std::io::Result<std::time::SystemTime>| {
+            time.ok().map(|time| now.duration_since(time).unwrap_or_default())
+        };
+
+        if let Some(HumanDuration(min_age)) = args.ignore_recent {
+            // a file still being written, or just moved in place, may have any of its times updated,
+            // so use the most recent
+            let recent = [TimeField::MTime, TimeField::CTime, TimeField::BTime]
+                .into_iter()
+                .filter_map(|field| age(file_time(metadata, field)))
+                .any(|age| age < min_age);
+
+            if recent {
+                self.observer.recent.fetch_add(1, Ordering::Relaxed);
+                return false;
+            }
+        }
+
+        if args.newer_than.is_some() || args.older_than.is_some() {
+            let time = file_time(metadata, args.time_field);
+
+            // if the time is unavailable, the file can't be in the window
+            let in_window = age(time).is_some_and(|age| {
+                args.newer_than.is_none_or(|HumanDuration(max)| age < max)
+                    && args.older_than.is_none_or(|HumanDuration(min)| age > min)
+            });
+
+            if !in_window {
+                self.observer.excluded.fetch_add(1, Ordering::Relaxed);
+                return false;
+            }
+        }
+
+        true
+    }
+
+    /// Handles a path given directly, rather than found by reading a directory.
+    /// Directories are only queued for reading if `dirs` is set.
+    fn visit_path(&self, path: &Path, dirs: bool, collected: &mut Collected) {
+        let args = self.args;
+
+        let res = path
+            .canonicalize()
+            .and_then(|path| std::fs::metadata(&path).map(|metadata| (path, metadata)));
+
+        let (path, mut metadata) = match res {
+            Ok(res) => res,
+            Err(e) => return self.error(path, e),
+        };
+
+        if metadata.is_symlink() {
+            if !args.follow_links {
+                return;
+            }
+
+            metadata = match std::fs::symlink_metadata(&path) {
+                Ok(m) => m,
+                Err(e) => return self.error(&path, e),
+            };
+        }
+
+        if metadata.is_file() && (args.min_size..=args.max_size).contains(&metadata.len()) {
+            if !self.accept_times(&metadata) {
+                return;
+            }
+
+            let Some((ext, named)) = args.detect_type(&path, self.observer) else {
+                return;
+            };
+
+            let Some(info) = self.accept_image(&path, ext, &metadata) else {
+                return;
+            };
+
+            if !self.accept_links(&path, &metadata) {
+                return;
+            }
+
+            self.found(FileEntry::new(path, ext, named, metadata).with_info(info), collected);
+        } else if dirs && metadata.is_dir() {
+            self.push_dir(0, path, &metadata, None, collected);
+        }
+    }
+
+    /// Checks the image dimensions and --where, reading the image header if needed for those or for sorting.
+    ///
+    /// Returns `None` if the file is rejected, otherwise the header properties if they were read.
+    fn accept_image(&self, path: &Path, ext: FileType, metadata: &std::fs::Metadata) -> Option<Option<ImageInfo>> {
+        let args = self.args;
+
+        let dimensions = args.filters_dimensions();
+        let filter_header = dimensions || args.where_expr.as_ref().is_some_and(Expr::needs_header);
+        let sort_header = args.sort.iter().any(|&(method, _)| method == SortMethod::Pixels);
+
+        if !filter_header && !sort_header && args.where_expr.is_none() {
+            return Some(None);
+        }
+
+        // archives have no header, so only properties of the file itself apply to them
+        let info = match (filter_header || sort_header) && ext != FileType::ZIP {
+            true => match super::probe::probe(path, ext) {
+                Ok(info) => Some(info),
+                Err(e) if filter_header => {
+                    self.error(path, std::io::Error::other(format!("Failed to read image header: {e}")));
+                    return None;
+                }
+                // unreadable headers only sort differently
+                Err(_) => None,
+            },
+            false => None,
+        };
+
+        let accepted = info
+            .as_ref()
+            .is_none_or(|i| !dimensions || args.width().contains(&i.width) && args.height().contains(&i.height))
+            && args.where_expr.as_ref().is_none_or(|expr| {
+                expr.matches(&Properties {
+                    info: info.as_ref(),
+                    metadata,
+                })
+            });
+
+        if !accepted {
+            self.observer.excluded.fetch_add(1, Ordering::Relaxed);
+            return None;
+        }
+
+        Some(info)
+    }
+
+    /// Applies --one-file-system, returning false if the entry is on another device than its parent directory.
+    fn same_device(&self, parent: Option<u64>, metadata: &std::fs::Metadata) -> bool {
+        if !self.args.one_file_system || parent.is_none() || device(metadata) == parent {
+            return true;
+        }
+
+        self.observer.excluded.fetch_add(1, Ordering::Relaxed);
+        false
+    }
+
+    /// Applies --hardlinks, returning true if the file should be converted through this path.
+    fn accept_links(&self, path: &Path, metadata: &std::fs::Metadata) -> bool {
+        let Some(id) = inode(metadata).filter(|_| link_count(metadata) > 1) else {
+            return true;
+        };
+
+        if self.args.hardlinks == HardlinkPolicy::Skip {
+            self.observer.linked.fetch_add(1, Ordering::Relaxed);
+            return false;
+        }
+
+        match self.links.entry_sync(id) {
+            scc::hash_map::Entry::Occupied(mut other) => {
+                other.get_mut().push(path.to_owned());
+                self.observer.linked.fetch_add(1, Ordering::Relaxed);
+                false
+            }
+            scc::hash_map::Entry::Vacant(first) => {
+                first.insert_entry(Vec::new());
+                true
+            }
+        }
+    }
+
+    fn push_dir(
+        &self,
+        depth: u64,
+        path: PathBuf,
+        metadata: &std::fs::Metadata,
+        rules: Option<Arc<IgnoreRules>>,
+        collected: &mut Collected,
+    ) {
+        let id = match inode(metadata) {
+            Some(id) => DirId::Inode(id),
+            None => DirId::Path(path.canonicalize().unwrap_or_else(|_| path.clone())),
+        };
+
+        if self.visited.insert_sync(id).is_err() {
+            return;
+        }
+
+        self.observer.dir_found.fetch_add(1, Ordering::Relaxed);
+
+        let device = device(metadata);
+
+        if let Some(device) = device {
+            collected.devices.entry(device).or_default().saw_dir(&path);
+        }
+
+        // must be incremented before pushing, so no thread can see an empty queue with nothing outstanding
+        self.outstanding.fetch_add(1, Ordering::AcqRel);
+        self.pending_dirs.push((depth, path, device, rules));
+    }
+
+    /// Adds the device tallies collected since the last flush to the shared ones.
+    fn flush_devices(&self, collected: &mut Collected) {
+        if collected.devices.is_empty() {
+            return;
+        }
+
+        let mut devices = self.observer.devices.lock().unwrap();
+
+        for (device, summary) in std::mem::take(&mut collected.devices) {
+            devices.entry(device).or_default().merge(&summary);
+        }
+    }
+
+    /// Reads directories from the queue until none are left, returning the files found by this thread.
+    fn run(&self) -> Vec<FileEntry> {
+        let mut collected = Collected::default();
+
+        while !self.stopped() {
+            let Some((depth, path, device, rules)) = self.pending_dirs.pop() else {
+                if self.outstanding.load(Ordering::Acquire) == 0 {
+                    break;
+                }
+
+                // other threads are still reading directories that may add more
+                std::thread::sleep(std::time::Duration::from_millis(1));
+                continue;
+            };
+
+            self.read_dir(depth, &path, device, rules, &mut collected);
+            self.flush_devices(&mut collected);
+
+            self.outstanding.fetch_sub(1, Ordering::AcqRel);
+        }
+
+        collected.files
+    }
+
+    /// Loads the ignore files in `dir`, if any, on top of the rules inherited from its parents.
+    fn load_ignore_rules(&self, dir: &Path, parent: Option<Arc<IgnoreRules>>) -> Option<Arc<IgnoreRules>> {
+        let mut builder = GitignoreBuilder::new(dir);
+
+        let names: &[&str] = match self.args.git_ignore {
+            true => &[IGNORE_FILE, ".gitignore", ".ignore"],
+            false => &[IGNORE_FILE],
+        };
+
+        for name in names {
+            match builder.add(dir.join(name)) {
+                Some(e) if e.io_error().is_some_and(|e| e.kind() == std::io::ErrorKind::NotFound) => {}
+                Some(e) => self.error(&dir.join(name), std::io::Error::other(e)),
+                None => {}
+            }
+        }
+
+        match builder.build() {
+            Ok(matcher) if !matcher.is_empty() => Some(Arc::new(IgnoreRules { matcher, parent })),
+            Ok(_) => parent,
+            Err(e) => {
+                self.error(dir, std::io::Error::other(e));
+                parent
+            }
+        }
+    }
+
+    /// Reads the directory at `path`, which is on `device`, queueing its subdirectories.
+    fn read_dir(
+        &self,
+        depth: u64,
+        path: &Path,
+        device: Option<u64>,
+        rules: Option<Arc<IgnoreRules>>,
+        collected: &mut Collected,
+    ) {
+        self.observer.dir_read.fetch_add(1, Ordering::Relaxed);
+
+        if depth > self.args.max_depth {
+            return;
+        }
+
+        let entries = match std::fs::read_dir(path) {
+            Ok(entries) => entries,
+            Err(e) => return self.error(path, e),
+        };
+
+        let rules = self.load_ignore_rules(path, rules);
+
+        for entry in entries {
+            let entry = match entry {
+                Ok(entry) => entry,
+                Err(e) => {
+                    self.error(path, e);
+                    continue;
+                }
+            };
+
+            let path = entry.path();
+
+            let ft = match entry.file_type() {
+                Ok(ft) => ft,
+                Err(e) => {
+                    self.error(&path, e);
+                    continue;
+                }
+            };
+
+            self.visit_entry(depth, path, ft, || entry.metadata(), device, &rules, collected);
+        }
+    }
+
+    /// Checks --hidden, ignore files and --include/--exclude, returning true if the entry should be skipped.
+    fn filtered(&self, path: &Path, ft: std::fs::FileType, rules: Option<&IgnoreRules>) -> bool {
+        let hidden = path
+            .file_name()
+            .is_some_and(|name| name.as_encoded_bytes().starts_with(b"."));
+
+        if !self.args.hidden && hidden {
+            return true;
+        }
+
+        // filter by extension only for files,
+        // before potentially expensive metadata calls
+        if ft.is_file() && !self.args.may_be_image(path) {
+            return true;
+        }
+
+        if rules.is_some_and(|rules| rules.is_ignored(path, ft.is_dir()))
+            || !self.patterns.is_empty() && !self.patterns.is_match(path, ft.is_dir())
+        {
+            self.observer.excluded.fetch_add(1, Ordering::Relaxed);
+            return true;
+        }
+
+        false
+    }
+
+    /// Handles an entry of a directory at `depth` on `device`, queueing it if it's a directory.
+    /// `entry_metadata` gets the metadata of the entry itself, only called if needed.
+    #[allow(clippy::too_many_arguments)]
+    fn visit_entry(
+        &self,
+        depth: u64,
+        path: PathBuf,
+        mut ft: std::fs::FileType,
+        entry_metadata: impl FnOnce() -> std::io::Result<std::fs::Metadata>,
+        device: Option<u64>,
+        rules: &Option<Arc<IgnoreRules>>,
+        collected: &mut Collected,
+    ) {
+        let args = self.args;
+
+        if self.filtered(&path, ft, rules.as_deref()) {
+            return;
+        }
+
+        // avoid computing metadata unless necessary
+        let mut metadata = None;
+
+        if ft.is_symlink() {
+            if !args.follow_links {
+                return;
+            }
+
+            // follows the link, unlike the entry's own metadata
+            let new_metadata = match std::fs::metadata(&path) {
+                Ok(m) => m,
+                Err(e) => return self.error(&path, e),
+            };
+
+            ft = new_metadata.file_type();
+            metadata = Some(new_metadata);
+        }
+
+        if ft.is_dir() {
+            if args.recurse {
+                let metadata = match metadata.map_or_else(entry_metadata, Ok) {
+                    Ok(m) => m,
+                    Err(e) => return self.error(&path, e),
+                };
+
+                if self.same_device(device, &metadata) {
+                    self.push_dir(depth + 1, path, &metadata, rules.clone(), collected);
+                }
+            }
+
+            return;
+        }
+
+        if !ft.is_file() || depth < args.min_depth {
+            return;
+        }
+
+        // the file may have vanished since it was listed
+        let metadata = match metadata.map_or_else(entry_metadata, Ok) {
+            Ok(m) => m,
+            Err(e) => return self.error(&path, e),
+        };
+
+        // files can only be on another device through a symlink
+        if !(args.min_size..=args.max_size).contains(&metadata.len())
+            || !self.same_device(device, &metadata)
+            || !self.accept_times(&metadata)
+        {
+            return;
+        }
+
+        // detect last, as it may need to read the file
+        let Some((ext, named)) = args.detect_type(&path, self.observer) else {
+            return;
+        };
+
+        let Some(info) = self.accept_image(&path, ext, &metadata) else {
+            return;
+        };
+
+        if !self.accept_links(&path, &metadata) {
+            return;
+        }
+
+        self.found(FileEntry::new(path, ext, named, metadata).with_info(info), collected);
+    }
+
+    /// Handles a path reported by --watch under `root`, a directory given as an argument,
+    /// applying the same checks as if it had been found by walking `root`.
+    fn visit_watched(&self, root: &Path, path: &Path) {
+        let args = self.args;
+
+        let Ok(relative) = path.strip_prefix(root) else {
+            return;
+        };
+
+        let components = Vec::from_iter(relative.components());
+
+        let Some((name, parents)) = components.split_last() else {
+            return;
+        };
+
+        if !args.recurse && !parents.is_empty() {
+            return;
+        }
+
+        let mut dir_device = match std::fs::metadata(root) {
+            Ok(metadata) => device(&metadata),
+            Err(e) => return self.error(root, e),
+        };
+
+        let mut rules = self.load_ignore_rules(root, None);
+        let mut dir = root.to_path_buf();
+
+        // the directories leading to the path must pass the same checks as when walking into them
+        for (depth, component) in parents.iter().enumerate() {
+            dir.push(component);
+
+            if depth as u64 + 1 > args.max_depth {
+                return;
+            }
+
+            let mut metadata = match std::fs::symlink_metadata(&dir) {
+                Ok(m) => m,
+                Err(e) => return self.error(&dir, e),
+            };
+
+            if self.filtered(&dir, metadata.file_type(), rules.as_deref()) {
+                return;
+            }
+
+            if metadata.is_symlink() {
+                if !args.follow_links {
+                    return;
+                }
+
+                metadata = match std::fs::metadata(&dir) {
+                    Ok(m) => m,
+                    Err(e) => return self.error(&dir, e),
+                };
+            }
+
+            if !self.same_device(dir_device, &metadata) {
+                return;
+            }
+
+            dir_device = device(&metadata);
+            rules = self.load_ignore_rules(&dir, rules);
+        }
+
+        let path = dir.join(name);
+
+        let metadata = match std::fs::symlink_metadata(&path) {
+            Ok(m) => m,
+            // removed again before it settled
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return,
+            Err(e) => return self.error(&path, e),
+        };
+
+        let depth = parents.len() as u64;
+        let mut collected = Collected::default();
+
+        self.visit_entry(
+            depth,
+            path,
+            metadata.file_type(),
+            || Ok(metadata),
+            dir_device,
+            &rules,
+            &mut collected,
+        );
+        self.flush_devices(&mut collected);
+
+        // a new directory is walked like during the scan, streaming its files
+        self.run();
+    }
+}
+
+/// Reads a list of paths from a file, or stdin if `list` is "-",
+/// separated by newlines, or by NUL bytes if `null` is set.
+fn read_file_list(list: &Path, null: bool) -> std::io::Result<Vec<PathBuf>> {
+    use std::io::Read;
+
+    let mut data = Vec::new();
+
+    if list == Path::new("-") {
+        std::io::stdin().lock().read_to_end(&mut data)?;
+    } else {
+        std::fs::File::open(list)?.read_to_end(&mut data)?;
+    }
+
+    let separator = if null { b'\0' } else { b'\n' };
+
+    let paths = data
+        .split(|&b| b == separator)
+        // tolerate lists written with Windows line endings
+        .map(|line| match null {
+            false => line.strip_suffix(b"\r").unwrap_or(line),
+            true => line,
+        })
+        .filter(|line| !line.is_empty())
+        .map(bytes_to_path)
+        .collect();
+
+    Ok(paths)
+}
+
+#[cfg(unix)]
+fn bytes_to_path(bytes: &[u8]) -> PathBuf {
+    use std::os::unix::ffi::OsStrExt;
+
+    PathBuf::from(OsStr::from_bytes(bytes))
+}
+
+#[cfg(not(unix))]
+fn bytes_to_path(bytes: &[u8]) -> PathBuf {
+    PathBuf::from(String::from_utf8_lossy(bytes).into_owned())
 }
 
 impl Conv2JxlArgs {
@@ -23,6 +837,12 @@ impl Conv2JxlArgs {
         self.quality = self.quality.clamp(0, 100);
         self.effort = self.effort.clamp(0, 10);
         self.randomize = self.randomize.clamp(0.0, 1.0);
+
+        // pick a seed up front, so it can be shown and the run repeated
+        if self.seed.is_none() && (self.randomize > 0.0 || self.sample.is_some()) {
+            self.seed = Some(rand::random());
+        }
+
         self.min_ratio = self.min_ratio.max(0.0);
         self.min_size = self.min_size.max(1); // always exclude empty files
 
@@ -39,173 +859,193 @@ impl Conv2JxlArgs {
         } else {
             self.parallel = self.parallel.max(1);
         }
-    }
-
-    pub fn scan(&self, observer: &ScanObserver) -> Result<ConversionState, Box<dyn std::error::Error>> {
-        let filter = self.filter.as_ref().map(|s| regex::Regex::new(s)).transpose()?;
-        let exclude = self.exclude.as_ref().map(|s| regex::Regex::new(s)).transpose()?;
 
-        let mut visited = std::collections::HashSet::<PathBuf, _>::with_capacity_and_hasher(
-            1024,
-            foldhash::fast::FixedState::default(),
-        );
+        if self.scan_threads == -1 {
+            self.scan_threads = self.parallel;
+        } else {
+            self.scan_threads = self.scan_threads.max(1);
+        }
+    }
 
-        let mut files: Vec<FileEntry> = Vec::new();
-        let mut current_files: Vec<FileEntry> = Vec::new();
-        let mut pending_dirs = Vec::new();
+    /// Cheap check on the extension alone, before any filesystem access,
+    /// to tell if the file at `path` could be an image of a type we want.
+    fn may_be_image(&self, path: &Path) -> bool {
+        let named = path
+            .extension()
+            .map(|ext| ext.to_str().and_then(|s| self.ext_map.get(s)));
 
-        for path in &self.paths {
-            let path = path.canonicalize()?;
+        match (self.detect, named) {
+            (DetectMethod::Extension, Some(Some(named))) => self.extensions.contains(&named),
+            // when sniffing, any known extension could hide a wanted type, as could no extension at all
+            (DetectMethod::Content, Some(Some(_)) | None) => true,
+            _ => false,
+        }
+    }
 
-            let mut metadata = std::fs::metadata(&path)?;
+    /// Determines the type to convert the file at `path` as, and the type implied by its extension.
+    ///
+    /// Returns `None` if the file is not of a type we want.
+    fn detect_type(&self, path: &Path, observer: &ScanObserver) -> Option<(FileType, Option<FileType>)> {
+        let named = path
+            .extension()
+            .and_then(OsStr::to_str)
+            .and_then(|s| self.ext_map.get(s));
 
-            if metadata.is_symlink() {
-                if !self.follow_links {
-                    continue;
-                }
+        let ext = match self.detect {
+            DetectMethod::Extension => named?,
+            // if the content isn't recognized, fall back to the extension and let cjxl decide
+            DetectMethod::Content => match super::detect::sniff(path) {
+                Ok(Some(sniffed)) => match named {
+                    Some(named) if named.is_compatible(sniffed) => named,
+                    _ => sniffed,
+                },
+                _ => named?,
+            },
+        };
 
-                metadata = std::fs::symlink_metadata(&path)?;
-            }
-
-            if metadata.is_file() && (self.min_size..=self.max_size).contains(&metadata.len()) {
-                let Some(ext) = path
-                    .extension()
-                    .and_then(OsStr::to_str)
-                    .and_then(|s| FileType::from_str(s).ok())
-                else {
-                    continue;
-                };
+        if !self.extensions.contains(&ext) {
+            return None;
+        }
 
-                if !self.extensions.contains(&ext) {
-                    continue;
-                }
+        if named.is_some_and(|named| !named.is_compatible(ext)) {
+            observer.mismatched.fetch_add(1, Ordering::Relaxed);
+        }
 
-                let f = observer.files.get(ext);
+        Some((ext, named))
+    }
 
-                f.found.fetch_add(1, Ordering::Relaxed);
-                f.bytes.fetch_add(metadata.len(), Ordering::Relaxed);
+    /// Walks the given paths, returning the files found and paths that could not be read,
+    /// unless streaming them to `stream`.
+    fn walk(
+        &self,
+        observer: &ScanObserver,
+        stream: Option<&ConversionState>,
+    ) -> Result<(Vec<FileEntry>, Vec<ScanError>), Box<dyn std::error::Error>> {
+        let walker = Walker::new(self, observer, stream)?;
 
-                files.push(FileEntry::new(path.clone(), ext, metadata));
-            } else if metadata.is_dir() && visited.insert(path.clone()) {
-                pending_dirs.push((0u64, path));
+        let mut collected = Collected::default();
 
-                observer.dir_found.fetch_add(1, Ordering::Relaxed);
-            }
+        for path in &self.paths {
+            walker.visit_path(path, true, &mut collected);
         }
 
-        let mut excluded = 0;
+        if let Some(ref list) = self.files_from {
+            // listed files are used as-is, without walking any directories
+            for path in read_file_list(list, self.null)? {
+                if walker.stopped() {
+                    break;
+                }
 
-        while let Some((depth, path)) = pending_dirs.pop() {
-            observer.dir_read.fetch_add(1, Ordering::Relaxed);
+                if !walker.patterns.is_empty() && !walker.patterns.is_match(&path, false) {
+                    observer.excluded.fetch_add(1, Ordering::Relaxed);
+                    continue;
+                }
 
-            if depth > self.max_depth {
-                continue;
+                walker.visit_path(&path, false, &mut collected);
             }
+        }
 
-            current_files.clear();
-
-            for entry in std::fs::read_dir(&path)? {
-                let entry = entry?;
-                let mut ft = entry.file_type()?;
+        walker.flush_devices(&mut collected);
 
-                // avoid computing metadata unless necessary
-                let mut ext = None;
-                let mut metadata = None;
+        let mut files = collected.files;
 
-                let path = entry.path();
+        std::thread::scope(|s| {
+            let workers = Vec::from_iter((0..self.scan_threads).map(|_| s.spawn(|| walker.run())));
 
-                // store and filter by extension only for files,
-                // before potentially expensive metadata calls
-                if ft.is_file() {
-                    ext = match path
-                        .extension()
-                        .and_then(OsStr::to_str)
-                        .and_then(|s| FileType::from_str(s).ok())
-                    {
-                        Some(ext) if self.extensions.contains(&ext) => Some(ext),
-                        _ => continue,
-                    };
-                }
+            for worker in workers {
+                files.append(&mut worker.join().expect("scan thread panicked"));
+            }
+        });
 
-                if (filter.is_some() || exclude.is_some())
-                    && let Some(path) = path.to_str()
-                    && (matches!(filter, Some(ref filter) if !filter.is_match(path))
-                        || matches!(exclude, Some(ref exclude) if exclude.is_match(path)))
+        // streaming doesn't support --hardlinks all, so links only need attaching to collected files
+        if self.hardlinks == HardlinkPolicy::All {
+            for file in &mut files {
+                if let Some(id) = inode(&file.metadata)
+                    && let Some((_, mut links)) = walker.links.remove_sync(&id)
                 {
-                    excluded += 1;
-                    continue;
-                }
-
-                if ft.is_symlink() {
-                    if !self.follow_links {
-                        continue;
-                    }
-
-                    let new_metadata = std::fs::symlink_metadata(&path)?;
-                    ft = new_metadata.file_type();
-                    metadata = Some(new_metadata);
+                    links.sort();
+                    file.links = links;
                 }
+            }
+        }
 
-                if ft.is_dir() {
-                    if self.recurse && visited.insert(path.clone()) {
-                        pending_dirs.push((depth + 1, path));
-                    }
+        Ok((files, walker.errors.into_inner().unwrap()))
+    }
 
-                    continue;
-                }
+    /// Compares files by each key of --sort in turn.
+    fn compare(&self, a: &FileEntry, b: &FileEntry) -> std::cmp::Ordering {
+        use std::cmp::Ordering;
 
-                if !ft.is_file() || depth < self.min_depth {
-                    continue;
-                }
+        let extension = |f: &FileEntry| f.path.extension().map(|e| e.to_ascii_lowercase());
+        let pixels = |f: &FileEntry| f.info.map(|i| i.width as u64 * i.height as u64);
 
-                let ext = ext.unwrap(); // must be Some() due to earlier check
+        for &(method, order) in self.sort.iter() {
+            let ordering = match method {
+                SortMethod::None => Ordering::Equal,
+                SortMethod::Size => a.metadata.len().cmp(&b.metadata.len()),
+                SortMethod::Name => natural_cmp(a.path.as_os_str(), b.path.as_os_str()),
+                SortMethod::Dir => natural_cmp(
+                    a.path.parent().unwrap_or(&a.path).as_os_str(),
+                    b.path.parent().unwrap_or(&b.path).as_os_str(),
+                ),
+                SortMethod::Ext => extension(a).cmp(&extension(b)),
+                SortMethod::Pixels => pixels(a).cmp(&pixels(b)),
+                SortMethod::Inode => inode(&a.metadata).cmp(&inode(&b.metadata)),
+                SortMethod::MTime => a.metadata.modified().ok().cmp(&b.metadata.modified().ok()),
+                SortMethod::CTime => changed(&a.metadata).ok().cmp(&changed(&b.metadata).ok()),
+                SortMethod::BTime => a.metadata.created().ok().cmp(&b.metadata.created().ok()),
+                SortMethod::ATime => a.metadata.accessed().ok().cmp(&b.metadata.accessed().ok()),
+            };
 
-                let metadata = match metadata {
-                    Some(m) => m,
-                    None => entry.metadata()?,
-                };
+            let ordering = match order.unwrap_or(self.sort_order) {
+                SortOrder::Asc => ordering,
+                SortOrder::Desc => ordering.reverse(),
+            };
 
-                if !(self.min_size..=self.max_size).contains(&metadata.len()) {
-                    continue;
-                }
+            if ordering.is_ne() {
+                return ordering;
+            }
+        }
 
-                let f = observer.files.get(ext);
+        Ordering::Equal
+    }
 
-                f.found.fetch_add(1, Ordering::Relaxed);
-                f.bytes.fetch_add(metadata.len(), Ordering::Relaxed);
+    pub fn scan(&self, observer: &ScanObserver) -> Result<ConversionState, Box<dyn std::error::Error>> {
+        let (mut files, errors) = self.walk(observer, None)?;
 
-                current_files.push(FileEntry::new(path, ext, metadata));
-            }
+        // the walk order depends on thread timing, so establish a deterministic order
+        // before any sorting, which is stable and keeps this order for ties
+        files.sort_by(|a, b| a.path.cmp(&b.path));
 
-            files.append(&mut current_files);
+        if !self.sort.is_empty() {
+            files.sort_by(|a, b| self.compare(a, b));
         }
 
-        match (self.sort, self.sort_order) {
-            (SortMethod::Name, SortOrder::Asc) => files.sort_by(|a, b| a.path.cmp(&b.path)),
-            (SortMethod::Name, SortOrder::Desc) => files.sort_by(|a, b| b.path.cmp(&a.path)),
+        // ChaCha is portable, so a seed gives the same result on every platform,
+        // and separate streams keep the sample the same regardless of --randomize
+        let rng = |stream| {
+            use rand::SeedableRng;
 
-            (SortMethod::Size, SortOrder::Asc) => files.sort_by_key(|f| f.metadata.len()),
-            (SortMethod::Size, SortOrder::Desc) => files.sort_by_key(|f| std::cmp::Reverse(f.metadata.len())),
+            let mut rng = rand_chacha::ChaCha8Rng::seed_from_u64(self.seed.unwrap_or_default());
+            rng.set_stream(stream);
+            rng
+        };
 
-            (SortMethod::ATime, SortOrder::Asc) => files.sort_by_key(|f| f.metadata.accessed().ok()),
-            (SortMethod::CTime, SortOrder::Asc) => files.sort_by_key(|f| f.metadata.created().ok()),
-            (SortMethod::MTime, SortOrder::Asc) => files.sort_by_key(|f| f.metadata.modified().ok()),
+        if let Some(sample) = self.sample {
+            let mut keep = vec![false; files.len()];
 
-            (SortMethod::ATime, SortOrder::Desc) => {
-                files.sort_by_key(|f| std::cmp::Reverse(f.metadata.accessed().ok()))
-            }
-            (SortMethod::CTime, SortOrder::Desc) => files.sort_by_key(|f| std::cmp::Reverse(f.metadata.created().ok())),
-            (SortMethod::MTime, SortOrder::Desc) => {
-                files.sort_by_key(|f| std::cmp::Reverse(f.metadata.modified().ok()))
+            for i in rand::seq::index::sample(&mut rng(0), files.len(), sample.of(files.len())) {
+                keep[i] = true;
             }
 
-            (SortMethod::None, _) => {}
+            let mut keep = keep.into_iter();
+            files.retain(|_| keep.next().unwrap_or(false));
         }
 
         if self.randomize > 0.0 {
-            use rand::{Rng, SeedableRng, rngs::SmallRng, seq::SliceRandom};
+            use rand::{Rng, seq::SliceRandom};
 
-            let mut rng = SmallRng::from_os_rng();
+            let mut rng = rng(1);
 
             if self.randomize >= 1.0 {
                 files.shuffle(&mut rng);
@@ -228,16 +1068,9 @@ impl Conv2JxlArgs {
             files.truncate(limit);
         }
 
-        let mut progress: PerFileType<Box<ConversionProgress>> = PerFileType::default();
-
         let mut final_counts = PerFileType::<(u64, u64)>::default(); // (count, bytes)
 
         for file in &files {
-            let progress = progress.get_mut(file.ext);
-
-            *progress.total_bytes.get_mut() += file.metadata.len();
-            progress.total += 1;
-
             let (count, bytes) = final_counts.get_mut(file.ext);
 
             *count += 1;
@@ -251,17 +1084,395 @@ impl Conv2JxlArgs {
             p.found.store(count, Ordering::Relaxed);
         }
 
-        Ok(ConversionState {
-            excluded,
-            files,
-            idx: AtomicUsize::new(0),
-            active: Vec::from_iter((0..self.parallel).map(|_| ThreadState {
-                file_idx: AtomicUsize::new(usize::MAX),
-                start_time: AtomicU64::new(0),
-            })),
-            non_success: Default::default(),
-            progress,
-            paused: Default::default(),
+        let mut devices = std::mem::take(&mut *observer.devices.lock().unwrap());
+
+        for summary in devices.values_mut() {
+            (summary.found, summary.bytes) = (0, 0);
+        }
+
+        for file in &files {
+            if let Some(summary) = device(&file.metadata).and_then(|device| devices.get_mut(&device)) {
+                summary.found += 1;
+                summary.bytes += file.metadata.len();
+            }
+        }
+
+        let conv = ConversionState::new(self.parallel as usize);
+
+        for file in files {
+            conv.push(file);
+        }
+
+        for error in errors {
+            conv.scan_errors.push(error);
+        }
+
+        conv.excluded
+            .store(observer.excluded.load(Ordering::Relaxed) as usize, Ordering::Relaxed);
+        conv.recent
+            .store(observer.recent.load(Ordering::Relaxed) as usize, Ordering::Relaxed);
+        *conv.devices.lock().unwrap() = devices;
+        conv.scan_complete.store(true, Ordering::Release);
+
+        Ok(conv)
+    }
+}
+
+impl SharedState {
+    /// Scans for files while they are being converted, pushing them to the conversion state as they are found.
+    pub fn scan_streaming(&self, observer: &ScanObserver) {
+        if let Err(e) = self.args.walk(observer, Some(&self.conv)) {
+            let _ = self.conv.scan_error.set(e.to_string());
+        }
+
+        self.conv
+            .excluded
+            .store(observer.excluded.load(Ordering::Relaxed) as usize, Ordering::Relaxed);
+        self.conv
+            .recent
+            .store(observer.recent.load(Ordering::Relaxed) as usize, Ordering::Relaxed);
+        *self.conv.devices.lock().unwrap() = std::mem::take(&mut *observer.devices.lock().unwrap());
+        self.conv.scan_complete.store(true, Ordering::Release);
+    }
+}
+
+/// A watcher for --watch, started before the scan so that files created while scanning aren't missed.
+pub struct Watch {
+    /// Stops watching when dropped
+    _watcher: notify::RecommendedWatcher,
+    events: std::sync::mpsc::Receiver<notify::Result<notify::Event>>,
+    /// The canonical input paths being watched
+    roots: Vec<PathBuf>,
+}
+
+impl Watch {
+    pub fn start(args: &Conv2JxlArgs) -> notify::Result<Self> {
+        use notify::{RecursiveMode, Watcher as _};
+
+        let (tx, events) = std::sync::mpsc::channel();
+        let mut watcher = notify::recommended_watcher(tx)?;
+
+        let mode = match args.recurse {
+            true => RecursiveMode::Recursive,
+            false => RecursiveMode::NonRecursive,
+        };
+
+        // paths that can't be read are reported by the scan
+        let roots = Vec::from_iter(args.paths.iter().filter_map(|path| path.canonicalize().ok()));
+
+        for root in &roots {
+            watcher.watch(root, mode)?;
+        }
+
+        Ok(Watch {
+            _watcher: watcher,
+            events,
+            roots,
         })
     }
 }
+
+impl SharedState {
+    /// Handles the events of the watcher for --watch once the scan completes, queueing images that are
+    /// created or changed once they have gone --settle without further changes. Runs until stopped.
+    pub fn watch(&self) {
+        let watch = self.conv.watch.lock().unwrap().take();
+
+        if let Some(watch) = watch
+            && let Err(e) = self.watch_paths(watch)
+        {
+            let _ = self.conv.watch_error.set(e.to_string());
+        }
+
+        self.conv.watching.store(false, Ordering::Release);
+    }
+
+    fn watch_paths(&self, watch: Watch) -> Result<(), Box<dyn std::error::Error>> {
+        use notify::{
+            EventKind,
+            event::{ModifyKind, RenameMode},
+        };
+
+        let (args, conv) = (&self.args, &self.conv);
+
+        // events from while scanning wait in the channel, since handling them before the scan has found
+        // every file could queue the same file twice
+        while !conv.scan_complete.load(Ordering::Acquire) {
+            if conv.stopping.load(Ordering::Relaxed) {
+                return Ok(());
+            }
+
+            std::thread::sleep(std::time::Duration::from_millis(50));
+        }
+
+        let Watch { events, roots, .. } = watch;
+
+        let observer = ScanObserver::default();
+        let walker = Walker::new(args, &observer, Some(conv))?;
+
+        // last event per path, and whether it could have been created rather than just changed
+        let mut pending: HashMap<PathBuf, (Instant, bool)> = HashMap::new();
+        let mut queued = Queued::default();
+        let mut settled = Vec::new();
+
+        while !conv.stopping.load(Ordering::Relaxed) {
+            match events.recv_timeout(std::time::Duration::from_millis(100)) {
+                Ok(Ok(event)) => {
+                    let created = match event.kind {
+                        EventKind::Create(_)
+                        | EventKind::Modify(ModifyKind::Name(RenameMode::To | RenameMode::Both)) => true,
+                        // only content changes matter, and --truncate changes the source's times itself
+                        EventKind::Modify(ModifyKind::Data(_) | ModifyKind::Any | ModifyKind::Other) => false,
+                        _ => continue,
+                    };
+
+                    let now = Instant::now();
+
+                    for path in event.paths {
+                        let entry = pending.entry(path).or_insert((now, false));
+
+                        entry.0 = now;
+                        entry.1 |= created;
+                    }
+                }
+                Ok(Err(e)) => {
+                    let path = e.paths.first().cloned().unwrap_or_default();
+
+                    walker.error(&path, std::io::Error::other(e));
+                }
+                Err(std::sync::mpsc::RecvTimeoutError::Timeout) => {}
+                Err(std::sync::mpsc::RecvTimeoutError::Disconnected) => break,
+            }
+
+            queued.sync(conv, args);
+            queued.prune(conv, args, &pending);
+
+            pending.retain(|path, &mut (time, created)| {
+                let keep = time.elapsed() < args.settle.0;
+
+                if !keep {
+                    settled.push((path.clone(), created));
+                }
+
+                keep
+            });
+
+            // a new directory comes before the files in it, which are queued by walking it
+            settled.sort_unstable();
+
+            for (path, created) in settled.drain(..) {
+                if queued.outputs.contains_key(&path) {
+                    continue;
+                }
+
+                let Ok(metadata) = std::fs::symlink_metadata(&path) else {
+                    continue; // removed before it settled
+                };
+
+                if metadata.is_dir() && !created {
+                    continue; // changes inside directories have their own events
+                }
+
+                // empty files are either still being written, or sources emptied by --truncate
+                if metadata.is_file() && (metadata.len() == 0 || queued.seen.get(&path) == Some(&stamp(&metadata))) {
+                    continue;
+                }
+
+                // the innermost watched path containing this one
+                let Some(root) = roots
+                    .iter()
+                    .filter(|root| path.starts_with(root))
+                    .max_by_key(|root| root.as_os_str().len())
+                else {
+                    continue;
+                };
+
+                if *root == path {
+                    // a file given as an argument
+                    let mut collected = Collected::default();
+
+                    walker.visit_path(&path, false, &mut collected);
+                    walker.flush_devices(&mut collected);
+                } else {
+                    walker.visit_watched(root, &path);
+                }
+
+                // the files in a new directory may have settled in the same batch
+                queued.sync(conv, args);
+            }
+
+            // links only need telling apart within a batch, so a file changed again later is queued again
+            walker.links.clear_sync();
+        }
+
+        Ok(())
+    }
+}
+
+/// Files queued while watching, so that events for them, including those from while scanning,
+/// don't queue them again.
+#[derive(Default)]
+struct Queued {
+    /// Size and modification time of queued files, or of archives once converted in place
+    seen: HashMap<PathBuf, Stamp>,
+    /// Outputs of queued files, which are never inputs, even when converting JXL, with the number of
+    /// queued files writing each. Archives are converted in place, so their own stamp is used instead.
+    outputs: HashMap<PathBuf, usize>,
+    /// Number of files in the conversion state recorded so far
+    known: usize,
+    /// Queued files that haven't finished converting
+    converting: Vec<usize>,
+    /// Files that finished converting, with when that was noticed and the stamp recorded for them
+    finished: VecDeque<(Instant, usize, Stamp)>,
+}
+
+impl Queued {
+    /// Records the files queued since the last call, and those that finished converting.
+    fn sync(&mut self, conv: &ConversionState, args: &Conv2JxlArgs) {
+        while self.known < conv.files.len() {
+            let file = &conv.files[self.known];
+            let output = file.output_path(args.no_preserve_extension);
+
+            if output != file.path {
+                *self.outputs.entry(output).or_default() += 1;
+            }
+
+            self.seen.insert(file.path.clone(), stamp(&file.metadata));
+            self.converting.push(self.known);
+            self.known += 1;
+        }
+
+        let now = Instant::now();
+
+        self.converting.retain(|&i| {
+            let file = &conv.files[i];
+
+            if file.state.get().is_none() {
+                return true;
+            }
+
+            let mut recorded = stamp(&file.metadata);
+
+            // the event for replacing an archive with its converted copy shouldn't queue it again,
+            // while later changes should
+            if file.ext == FileType::ZIP
+                && let Ok(metadata) = std::fs::metadata(&file.path)
+                && self.seen.get(&file.path) == Some(&recorded)
+            {
+                recorded = stamp(&metadata);
+                self.seen.insert(file.path.clone(), recorded);
+            }
+
+            self.finished.push_back((now, i, recorded));
+            false
+        });
+    }
+
+    /// Forgets files that finished converting at least --settle ago, once no event for them is pending.
+    fn prune(&mut self, conv: &ConversionState, args: &Conv2JxlArgs, pending: &HashMap<PathBuf, (Instant, bool)>) {
+        while let Some(&(time, i, recorded)) = self.finished.front()
+            && time.elapsed() >= args.settle.0
+        {
+            let file = &conv.files[i];
+            let output = file.output_path(args.no_preserve_extension);
+
+            if pending.contains_key(&file.path) || pending.contains_key(&output) {
+                break;
+            }
+
+            self.finished.pop_front();
+
+            // unless queued again since
+            if self.seen.get(&file.path) == Some(&recorded) {
+                self.seen.remove(&file.path);
+            }
+
+            if let Some(count) = self.outputs.get_mut(&output) {
+                *count -= 1;
+
+                if *count == 0 {
+                    self.outputs.remove(&output);
+                }
+            }
+        }
+    }
+}
+
+/// Size and modification time of a file, to tell if it changed since it was queued.
+type Stamp = (u64, Option<std::time::SystemTime>);
+
+fn stamp(metadata: &std::fs::Metadata) -> Stamp {
+    (metadata.len(), metadata.modified().ok())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn device_tallies_merge() {
+        let mut shared = DeviceSummary::default();
+
+        let mut a = DeviceSummary::default();
+        a.saw_dir(Path::new("/mnt/photos/2024"));
+        a.saw_dir(Path::new("/mnt/photos/2024/raw"));
+        (a.found, a.bytes) = (2, 100);
+
+        let mut b = DeviceSummary::default();
+        b.saw_dir(Path::new("/mnt/photos"));
+        (b.found, b.bytes) = (1, 50);
+
+        shared.merge(&a);
+        assert_eq!(shared.root, Path::new("/mnt/photos/2024"));
+
+        shared.merge(&b);
+        shared.merge(&DeviceSummary::default());
+
+        assert_eq!(shared.root, Path::new("/mnt/photos"));
+        assert_eq!((shared.found, shared.bytes), (3, 150));
+    }
+
+    #[test]
+    fn queued_files_are_forgotten() {
+        let dir = tempfile::tempdir().unwrap();
+        let (png, zip) = (dir.path().join("a.png"), dir.path().join("b.zip"));
+
+        std::fs::write(&png, [0; 100]).unwrap();
+        std::fs::write(&zip, b"original").unwrap();
+
+        let conv = ConversionState::new(1);
+
+        for (path, ftype) in [(&png, FileType::PNG), (&zip, FileType::ZIP)] {
+            let metadata = std::fs::metadata(path).unwrap();
+            conv.push(FileEntry::new(path.clone(), ftype, Some(ftype), metadata));
+        }
+
+        let args: Conv2JxlArgs = argh::FromArgs::from_args(&["conv2jxl"], &["--settle", "0s"]).unwrap();
+        let mut queued = Queued::default();
+
+        queued.sync(&conv, &args);
+
+        // archives are their own output
+        assert_eq!(Vec::from_iter(queued.outputs.keys()), [&dir.path().join("a.png.jxl")]);
+
+        std::fs::write(&zip, b"converted in place").unwrap();
+
+        conv.files[0].set_state(Instant::now(), ConversionOutcome::Skipped);
+        conv.files[1].set_state(Instant::now(), ConversionOutcome::Success(8, 18));
+
+        queued.sync(&conv, &args);
+
+        // so the event for replacing the archive doesn't queue it again
+        assert_eq!(queued.seen.get(&zip), Some(&stamp(&std::fs::metadata(&zip).unwrap())));
+
+        // kept while an event for it is still settling
+        queued.prune(&conv, &args, &HashMap::from([(zip.clone(), (Instant::now(), true))]));
+
+        assert_eq!(Vec::from_iter(queued.seen.keys()), [&zip]);
+        assert!(queued.outputs.is_empty());
+
+        queued.prune(&conv, &args, &HashMap::new());
+
+        assert!(queued.seen.is_empty() && queued.finished.is_empty());
+    }
+}
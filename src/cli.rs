@@ -3,6 +3,8 @@ use std::ops::{Deref, RangeInclusive};
 use std::path::PathBuf;
 use std::{error::Error, fmt::Display, str::FromStr};
 
+use crate::app::expr::Expr;
+
 /// Convert files and directories to JPEG XL format.
 #[derive(argh::FromArgs, Debug)]
 pub struct Conv2JxlArgs {
@@ -10,6 +12,12 @@ pub struct Conv2JxlArgs {
     #[argh(switch, short = 'U')]
     pub no_unicode: bool,
 
+    /// run without the interactive interface, printing a line for each file instead.
+    /// Implied when not run from a terminal. SIGINT or SIGTERM stops after the files in progress,
+    /// and a second one stops right away.
+    #[argh(switch)]
+    pub no_tui: bool,
+
     /// process subdirectories recursively
     #[argh(switch, short = 'r')]
     pub recurse: bool,
@@ -17,20 +25,61 @@ pub struct Conv2JxlArgs {
     /// maximum recursion depth. Default is unlimited.
     /// Only applies if --recurse is set.
     /// A depth of 0 means only the given directories, 1 means their direct subdirectories, etc.
-    /// Note that --ignore-recent only applies to files in the direct subdirectories of the given paths, not further down.
     #[argh(option, default = "u64::MAX")]
     pub max_depth: u64,
 
+    /// include hidden files and directories, i.e. those starting with a dot, when recursing.
+    /// Paths given directly are always included.
+    #[argh(switch, short = 'H')]
+    pub hidden: bool,
+
+    /// also honor .gitignore and .ignore files when recursing.
+    /// .conv2jxlignore files, using the same syntax, are always honored.
+    #[argh(switch)]
+    pub git_ignore: bool,
+
+    /// don't descend into directories on other filesystems when recursing, such as mounted drives or network shares.
+    /// Paths given directly are always scanned, even if they are on different filesystems. Unix only.
+    #[argh(switch, short = 'x')]
+    pub one_file_system: bool,
+
     /// follow symbolic links when recursing and processing files.
     #[argh(switch)]
     pub follow_links: bool,
 
+    /// what to do with files that have more than one hardlink, which are recognized no matter which link is found.
+    /// "once" (default) converts the file through the first link found and leaves the other links alone,
+    /// "skip" doesn't convert such files at all, and "all" also gives every other link a hardlink to the converted file,
+    /// deleting those links too with --delete. Files found by --watch after the scan are handled as with "once".
+    /// Unix only: elsewhere every link is treated as a separate file, so only the default is accepted.
+    #[argh(option, default = "HardlinkPolicy::Once")]
+    pub hardlinks: HardlinkPolicy,
+
     /// minimum recursion depth. Default is 0.
     /// Only applies if --recurse is set.
     /// A depth of 0 means only the given directories, 1 means their direct subdirectories, etc.
     #[argh(option, default = "0")]
     pub min_depth: u64,
 
+    /// skip files modified, changed or created less than this long ago, e.g. "10m" or "1h30m",
+    /// to avoid converting files that another program may still be writing.
+    /// Durations are a number with a unit of ms, s, m, h, d or w, and can be combined. A bare number is in seconds.
+    #[argh(option)]
+    pub ignore_recent: Option<HumanDuration>,
+
+    /// only convert files whose time (see --time-field) is less than this long ago, e.g. "7d".
+    #[argh(option)]
+    pub newer_than: Option<HumanDuration>,
+
+    /// only convert files whose time (see --time-field) is more than this long ago, e.g. "30d".
+    #[argh(option)]
+    pub older_than: Option<HumanDuration>,
+
+    /// which file time --newer-than and --older-than apply to, either "mtime" (modification time, default),
+    /// "ctime" (status change time, unix only) or "btime" (creation time, where the filesystem records it).
+    #[argh(option, default = "TimeField::MTime")]
+    pub time_field: TimeField,
+
     /// overwrite existing files.
     #[argh(switch, short = 'O')]
     pub overwrite: bool,
@@ -90,24 +139,50 @@ pub struct Conv2JxlArgs {
     #[argh(option, short = 'M', default = "u64::MAX")]
     pub max_size: u64,
 
-    /// only convert images with width larger than or equal to this value. Default is 0 (no minimum).
+    /// only convert images with width larger than or equal to this value, checked while scanning. Default is 0 (no minimum).
     #[argh(option, default = "0")]
     pub min_width: u32,
     /// only convert images with width smaller than or equal to this value. Default is unlimited 2^32-1.
     #[argh(option, default = "u32::MAX")]
     pub max_width: u32,
 
-    /// only convert images with height larger than or equal to this value. Default is 0 (no minimum).
+    /// only convert images with height larger than or equal to this value, checked while scanning. Default is 0 (no minimum).
     #[argh(option, default = "0")]
     pub min_height: u32,
     /// only convert images with height smaller than or equal to this value. Default is unlimited 2^32-1.
     #[argh(option, default = "u32::MAX")]
     pub max_height: u32,
 
+    /// only convert images for which this expression is true, checked while scanning by reading the image header.
+    /// Properties are width, height, pixels, aspect, bit_depth, channels, has_alpha, animated, color
+    /// (gray, gray_alpha, rgb, rgba, palette or cmyk), size (in bytes) and mtime (time since modification, in seconds).
+    /// Numbers can have a size unit (B, KB, MB, GB, TB, KiB, MiB, GiB, TiB, or K, G and T for powers of 1000)
+    /// or a duration unit (ms, s, min, h, d, w), e.g. 10MB, 2MiB, 1.5K or 30d.
+    /// A bare "m" is rejected as ambiguous, use "min" for minutes or "MB" or "1e6" otherwise.
+    /// Comparisons are ==, !=, <, <=, > and >=,
+    /// combined with &&/and, ||/or, !/not and parentheses,
+    /// e.g. "pixels >= 1e6 && !animated && (color == rgb || color == rgba)".
+    /// Comparisons with properties that can't be read for a format are false.
+    #[argh(option, long = "where")]
+    pub where_expr: Option<Expr>,
+
     /// limit the number of files to convert. Default is no limit.
     #[argh(option, short = 'l')]
     pub limit: Option<usize>,
 
+    /// stop handing out new files once conversion has run for this long, e.g. "2h" or "1h30m".
+    /// Files already being converted are finished.
+    #[argh(option)]
+    pub time_budget: Option<HumanDuration>,
+
+    /// stop handing out new files once the next one would bring the total input size over this budget,
+    /// e.g. "500GB" or "2TiB". Units are B, KB, MB, GB, TB (powers of 1000) and KiB, MiB, GiB, TiB (powers of 1024),
+    /// the same as in --where.
+    /// Files skipped because their output exists, or replayed from --journal, don't count.
+    /// Files already being converted are finished.
+    #[argh(option)]
+    pub byte_budget: Option<ByteSize>,
+
     /// use lossless recompression of JPEG files. Default is true.
     /// Set to false to allow lossy recompression of JPEG files.
     #[argh(option, short = 'j', default = "true")]
@@ -121,35 +196,88 @@ pub struct Conv2JxlArgs {
     #[argh(switch)]
     pub disable_jpeg_reconstruction: bool,
 
-    /// filter input images as comma-separated list of file extensions.
+    /// filter input images as comma-separated list of file types.
     /// Defaults to "png", which means only PNG files will be processed.
-    /// Use "*" to process all supported files.
+    /// Use "*" to process all supported image files. Use "zip" for ZIP and CBZ archives,
+    /// whose image entries are converted into a new archive that replaces the original.
+    /// Only entries of the other types given are converted, or every image type if "zip" is the only one.
+    /// Archives with nothing to convert are left as they are.
     #[argh(option, long = "ext", default = "FileTypes::default()")]
     pub extensions: FileTypes,
 
+    /// additional file extensions to recognize, as a comma-separated list of ext=type pairs,
+    /// e.g. "jfif=jpeg,jpe=jpeg,pbm=pnm". These take precedence over the built-in extensions.
+    #[argh(option, default = "ExtensionMap::default()")]
+    pub ext_map: ExtensionMap,
+
+    /// how to determine the type of input files.
+    /// Valid values are "extension" (default), which only looks at the file extension,
+    /// and "content", which reads the first bytes of each file to detect its real type.
+    /// With "content", files without an extension are considered as well, and files whose extension
+    /// does not match their content are converted according to their content, with a warning.
+    #[argh(option, default = "DetectMethod::Extension")]
+    pub detect: DetectMethod,
+
     /// removes original file extension from .<ext>.jxl and just uses .jxl
     #[argh(switch, short = 'X')]
     pub no_preserve_extension: bool,
 
-    /// filter input images by regex pattern on full path.
-    /// Only files matching the pattern will be processed.
+    /// only process files whose full path matches any of these patterns. Can be given multiple times.
+    /// Patterns are regular expressions, or globs if prefixed with "glob:", e.g. "glob:**/photos/*.png".
+    /// Matching is done on the raw path bytes, so paths that aren't valid UTF-8 are filtered as well.
     #[argh(option)]
-    pub filter: Option<String>,
+    pub include: Vec<String>,
 
-    /// exclude input images by regex pattern on full path.
-    /// Files matching the pattern will be skipped.
+    /// same as --include, kept for compatibility.
     #[argh(option)]
-    pub exclude: Option<String>,
+    pub filter: Vec<String>,
 
-    /// path to error log, for which errors will be appended
+    /// skip files and directories whose full path matches any of these patterns. Can be given multiple times.
+    /// Uses the same syntax as --include.
+    #[argh(option)]
+    pub exclude: Vec<String>,
+
+    /// path to error log, for which errors will be appended.
+    /// Each line is a JSON object for a file that failed, was inefficient or converted with a warning.
     #[argh(option)]
     pub error_log: Option<PathBuf>,
 
-    /// interval (in files processed) to print a summary of progress.
-    /// Default is no summary.
+    /// also log files converted without issue or skipped to --error-log.
+    #[argh(switch)]
+    pub error_log_all: bool,
+
+    /// keep a journal of outcomes in this file across runs, keyed by path, size and modification time.
+    /// Files an earlier run found inefficient or failed to convert with the same settings are skipped,
+    /// and outputs left by a run that was killed mid-conversion are converted again.
+    #[argh(option)]
+    pub journal: Option<PathBuf>,
+
+    /// also key the journal on a hash of each file's content, which catches changes that keep the size
+    /// and modification time, at the cost of reading every file an extra time.
+    #[argh(switch)]
+    pub journal_hash: bool,
+
+    /// write a report of every file found and its outcome to this file when the run ends,
+    /// with totals per file type and the settings used.
+    #[argh(option)]
+    pub report: Option<PathBuf>,
+
+    /// format of --report: "json", or "csv" for a row per file,
+    /// with the totals and settings written to a .summary.json file next to it.
+    /// Defaults to "csv" if the report file ends with .csv, and "json" otherwise.
+    #[argh(option)]
+    pub report_format: Option<ReportFormat>,
+
+    /// interval (in files processed) to print a summary of progress,
+    /// shown in a history panel above the file list, or printed without the TUI. Default is no summary.
     #[argh(option)]
     pub summary_interval: Option<usize>,
 
+    /// path to append the --summary-interval lines to instead of printing them,
+    /// when running without the TUI.
+    #[argh(option)]
+    pub summary_log: Option<PathBuf>,
+
     /// number of threads each conversion process should use.
     /// Use -1 to use all available threads, 0 (default) for single-threaded.
     #[argh(option, short = 't', default = "0")]
@@ -160,17 +288,26 @@ pub struct Conv2JxlArgs {
     #[argh(option, short = 'p', default = "-1")]
     pub parallel: i32,
 
+    /// number of threads to use for scanning directories.
+    /// Use -1 (default) to use the same number as --parallel. Minimum is 1 if set.
+    /// Scanning network shares can benefit from using more threads than there are cores.
+    #[argh(option, default = "-1")]
+    pub scan_threads: i32,
+
     /// use progressive encoding for JPEG XL files.
     #[argh(switch)]
     pub progressive: bool,
 
-    /// sort files before conversion.
-    /// Valid values are "none", "asc", "desc", "name", "mtime", "ctime", "atime".
-    /// "asc" and "desc" sort by file size. Default is "none".
-    #[argh(option, short = 's', default = "SortMethod::None")]
-    pub sort: SortMethod,
+    /// sort files before conversion, by a comma-separated list of keys, each optionally followed by ":asc" or ":desc",
+    /// e.g. "dir,pixels:desc,name". Later keys break ties of earlier ones.
+    /// Valid keys are "none", "size", "name" (full path, with numbers in natural order, so img2 comes before img10),
+    /// "dir" (parent directory, in natural order), "ext" (extension), "pixels" (width times height, read while scanning),
+    /// "inode" (device and inode number, close to the physical order on disk for many filesystems, unix only),
+    /// "mtime", "ctime" (status change time, unix only), "btime" (creation time) and "atime". Default is "none".
+    #[argh(option, short = 's', default = "SortSpec::default()")]
+    pub sort: SortSpec,
 
-    /// sort direction, either "asc" or "desc". Default is "asc".
+    /// default sort direction for keys of --sort without one, either "asc" or "desc". Default is "asc".
     #[argh(option, short = 'd', default = "SortOrder::Asc")]
     pub sort_order: SortOrder,
 
@@ -179,9 +316,90 @@ pub struct Conv2JxlArgs {
     #[argh(option, default = "0.0")]
     pub randomize: f64,
 
+    /// seed for --randomize and --sample, so the same files are picked in the same order on every run.
+    /// If not set, a random seed is used, which is shown in the statistics so the run can be repeated.
+    #[argh(option)]
+    pub seed: Option<u64>,
+
+    /// only convert a random sample of the files found, either a number of files, e.g. "100",
+    /// or a percentage, e.g. "5%". Files keep their sorted order, and are sampled before --randomize and --limit.
+    #[argh(option)]
+    pub sample: Option<Sample>,
+
+    /// start converting files as soon as they are found, instead of waiting for the scan to complete.
+    /// Totals and ETA are provisional until the scan completes.
+    /// Has no effect if --sort, --randomize, --sample, --limit or --hardlinks all are used,
+    /// as those need all files up front.
+    #[argh(switch)]
+    pub stream: bool,
+
+    /// after the initial pass, keep watching the given paths and convert images that are created or changed,
+    /// with the same filters and options. Runs until stopped. Paths from --files-from are not watched.
+    #[argh(switch)]
+    pub watch: bool,
+
+    /// with --watch, how long a file must go without changes before it is converted,
+    /// so files still being written or copied are left alone, e.g. "2s" or "1m". Default is 2s.
+    #[argh(option, default = "HumanDuration(std::time::Duration::from_secs(2))")]
+    pub settle: HumanDuration,
+
+    /// read paths of files to convert from this file, or from stdin if "-", one per line.
+    /// Listed files go through the same type detection, filters, sorting and limit as scanned files,
+    /// but listed directories are not walked. Can be combined with paths given as arguments.
+    #[argh(option)]
+    pub files_from: Option<PathBuf>,
+
+    /// paths in --files-from are separated by NUL bytes instead of newlines,
+    /// as written by `find -print0` or `fd -0`.
+    #[argh(switch, short = '0')]
+    pub null: bool,
+
     /// paths to files or directories to convert.
     #[argh(positional, greedy)]
     pub paths: Vec<PathBuf>,
+
+    #[argh(subcommand)]
+    pub command: Option<Command>,
+}
+
+/// Alternatives to converting, which take the same options to select files.
+#[derive(argh::FromArgs, Debug)]
+#[argh(subcommand)]
+pub enum Command {
+    Estimate(EstimateArgs),
+    Restore(RestoreArgs),
+}
+
+/// estimate the savings and time of converting the files found, by converting a random sample
+/// of each file type and size range into a temporary directory. Nothing is modified.
+/// Options to select and convert files go before "estimate".
+#[derive(argh::FromArgs, Debug)]
+#[argh(subcommand, name = "estimate")]
+pub struct EstimateArgs {
+    /// number of files to convert for each file type and size range. Default is 5.
+    /// More samples narrow the confidence intervals, but take longer.
+    #[argh(option, default = "5")]
+    pub samples: usize,
+
+    /// paths to files or directories to estimate, in addition to those given before "estimate".
+    #[argh(positional, greedy)]
+    pub paths: Vec<PathBuf>,
+}
+
+/// restore converted files to their original format with djxl. Finds ".<ext>.jxl" files,
+/// and ".jxl" files next to the empty file left by --truncate, which is replaced. JPEGs are reconstructed
+/// bit-exactly when the JPEG XL file kept the reconstruction data, and originals still present are left alone.
+/// Options to select files go before "restore".
+#[derive(argh::FromArgs, Debug)]
+#[argh(subcommand, name = "restore")]
+pub struct RestoreArgs {
+    /// delete each JPEG XL file once the original is restored.
+    #[argh(switch)]
+    pub delete: bool,
+
+    /// paths to files or directories to restore, in addition to those given before "restore".
+    #[argh(positional, greedy)]
+    pub paths: Vec<PathBuf>,
 }
 
 impl Conv2JxlArgs {
@@ -192,6 +410,21 @@ impl Conv2JxlArgs {
     pub fn height(&self) -> RangeInclusive<u32> {
         self.min_height..=self.max_height
     }
+
+    /// Returns true if any of the image dimension filters are set.
+    pub fn filters_dimensions(&self) -> bool {
+        self.min_width > 0 || self.min_height > 0 || self.max_width < u32::MAX || self.max_height < u32::MAX
+    }
+
+    /// Returns true if conversion can start while scanning.
+    pub fn streaming(&self) -> bool {
+        self.stream
+            && self.sort.is_empty()
+            && self.randomize == 0.0
+            && self.sample.is_none()
+            && self.limit.is_none()
+            && self.hardlinks != HardlinkPolicy::All
+    }
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
@@ -202,12 +435,100 @@ pub enum SortMethod {
     Name,
     /// Modification time
     MTime,
-    /// Creation time
+    /// Status change time, unix only
     CTime,
+    /// Creation (birth) time
+    BTime,
     /// Accessed time
     ATime,
+    /// Width times height
+    Pixels,
+    /// Parent directory
+    Dir,
+    /// File extension
+    Ext,
+    /// Device and inode number
+    Inode,
+}
+
+/// Sort keys, each with an optional direction overriding --sort-order.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct SortSpec(pub Vec<(SortMethod, Option<SortOrder>)>);
+
+impl Deref for SortSpec {
+    type Target = [(SortMethod, Option<SortOrder>)];
+
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum DetectMethod {
+    /// Only use the file extension
+    #[default]
+    Extension,
+    /// Sniff magic bytes at the start of the file
+    Content,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum HardlinkPolicy {
+    /// Convert through the first link found only
+    #[default]
+    Once,
+    /// Don't convert files with more than one link
+    Skip,
+    /// Convert once, and link the output next to every link
+    All,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ReportFormat {
+    /// A single document with totals, settings and every file
+    #[default]
+    Json,
+    /// A row per file
+    Csv,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum TimeField {
+    /// Modification time
+    #[default]
+    MTime,
+    /// Status change time, unix only
+    CTime,
+    /// Creation (birth) time
+    BTime,
+}
+
+/// Size of a random sample of files
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Sample {
+    Count(usize),
+    /// Fraction of all files, between 0.0 and 1.0
+    Fraction(f64),
+}
+
+impl Sample {
+    /// Returns the number of files to pick out of `len`.
+    pub fn of(self, len: usize) -> usize {
+        match self {
+            Sample::Count(n) => n.min(len),
+            Sample::Fraction(f) => ((len as f64 * f).round() as usize).min(len),
+        }
+    }
 }
 
+/// A number of bytes parsed from a human-readable string, e.g. "500GB"
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct ByteSize(pub u64);
+
+/// A duration parsed from a human-readable string, e.g. "1h30m"
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct HumanDuration(pub std::time::Duration);
+
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
 pub enum SortOrder {
     #[default]
@@ -267,7 +588,9 @@ macro_rules! decl_filetypes {
     };
 }
 
-decl_filetypes!(JXL, PPM, PNM, PFM, PAM, PGX, PNG, APNG, GIF, JPEG, TIFF, TGA, QOI, BMP);
+decl_filetypes!(
+    JXL, PPM, PNM, PFM, PAM, PGX, PNG, APNG, GIF, JPEG, TIFF, TGA, QOI, BMP, ZIP
+);
 
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub struct FileTypes(pub HashSet<FileType, foldhash::fast::FixedState>);
@@ -297,6 +620,30 @@ pub struct InvalidSortDirection;
 #[derive(Debug, Clone, Copy)]
 pub struct InvalidFileType;
 
+#[derive(Debug, Clone, Copy)]
+pub struct InvalidDetectMethod;
+
+#[derive(Debug, Clone, Copy)]
+pub struct InvalidExtensionMap;
+
+#[derive(Debug, Clone, Copy)]
+pub struct InvalidTimeField;
+
+#[derive(Debug, Clone, Copy)]
+pub struct InvalidDuration;
+
+#[derive(Debug, Clone, Copy)]
+pub struct InvalidHardlinkPolicy;
+
+#[derive(Debug, Clone, Copy)]
+pub struct InvalidSample;
+
+#[derive(Debug, Clone, Copy)]
+pub struct InvalidByteSize;
+
+#[derive(Debug, Clone, Copy)]
+pub struct InvalidReportFormat;
+
 impl Display for InvalidSortMethod {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         f.write_str("invalid sort method")
@@ -315,21 +662,86 @@ impl Display for InvalidFileType {
     }
 }
 
+impl Display for InvalidDetectMethod {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("invalid detection method")
+    }
+}
+
+impl Display for InvalidExtensionMap {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("invalid extension mapping, expected a comma-separated list of ext=type pairs")
+    }
+}
+
+impl Display for InvalidTimeField {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("invalid time field")
+    }
+}
+
+impl Display for InvalidDuration {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("invalid duration, expected e.g. \"90s\", \"10m\" or \"1h30m\"")
+    }
+}
+
+impl Display for InvalidHardlinkPolicy {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("invalid hardlink policy")
+    }
+}
+
+impl Display for InvalidSample {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("invalid sample size, expected a number of files or a percentage, e.g. \"100\" or \"5%\"")
+    }
+}
+
+impl Display for InvalidByteSize {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("invalid size, expected e.g. \"500GB\" or \"2TiB\"")
+    }
+}
+
+impl Display for InvalidReportFormat {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("invalid report format, expected \"json\" or \"csv\"")
+    }
+}
+
 impl Error for InvalidSortMethod {}
 impl Error for InvalidSortDirection {}
 impl Error for InvalidFileType {}
+impl Error for InvalidDetectMethod {}
+impl Error for InvalidExtensionMap {}
+impl Error for InvalidTimeField {}
+impl Error for InvalidDuration {}
+impl Error for InvalidHardlinkPolicy {}
+impl Error for InvalidSample {}
+impl Error for InvalidByteSize {}
+impl Error for InvalidReportFormat {}
 
 impl FromStr for SortMethod {
     type Err = InvalidSortMethod;
 
     fn from_str(s: &str) -> Result<Self, Self::Err> {
-        const PATTERNS: [(&str, SortMethod); 6] = [
+        const PATTERNS: [(&str, SortMethod); 15] = [
             ("none", SortMethod::None),
             ("size", SortMethod::Size),
             ("name", SortMethod::Name),
             ("mtime", SortMethod::MTime),
             ("ctime", SortMethod::CTime),
+            ("btime", SortMethod::BTime),
+            ("created", SortMethod::BTime),
             ("atime", SortMethod::ATime),
+            ("pixels", SortMethod::Pixels),
+            ("dir", SortMethod::Dir),
+            ("directory", SortMethod::Dir),
+            ("ext", SortMethod::Ext),
+            ("extension", SortMethod::Ext),
+            ("inode", SortMethod::Inode),
+            ("physical", SortMethod::Inode),
         ];
 
         for (pattern, method) in PATTERNS {
@@ -342,6 +754,28 @@ impl FromStr for SortMethod {
     }
 }
 
+impl FromStr for SortSpec {
+    type Err = InvalidSortMethod;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut keys = Vec::new();
+
+        for key in s.split(',').map(str::trim).filter(|k| !k.is_empty()) {
+            let (method, order) = match key.split_once(':') {
+                Some((method, order)) => (method, Some(order.parse().map_err(|_| InvalidSortMethod)?)),
+                None => (key, None),
+            };
+
+            match method.parse()? {
+                SortMethod::None => {}
+                method => keys.push((method, order)),
+            }
+        }
+
+        Ok(SortSpec(keys))
+    }
+}
+
 impl FromStr for SortOrder {
     type Err = InvalidSortDirection;
 
@@ -367,29 +801,235 @@ impl FromStr for SortOrder {
     }
 }
 
-impl FromStr for FileType {
-    type Err = InvalidFileType;
+impl FromStr for TimeField {
+    type Err = InvalidTimeField;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        const PATTERNS: [(&str, TimeField); 6] = [
+            ("mtime", TimeField::MTime),
+            ("modified", TimeField::MTime),
+            ("ctime", TimeField::CTime),
+            ("changed", TimeField::CTime),
+            ("btime", TimeField::BTime),
+            ("created", TimeField::BTime),
+        ];
+
+        for (pattern, field) in PATTERNS {
+            if s.eq_ignore_ascii_case(pattern) {
+                return Ok(field);
+            }
+        }
+
+        Err(InvalidTimeField)
+    }
+}
+
+/// Multipliers for size suffixes, shared by every option and --where, and matched case-insensitively.
+/// A bare K, M, G or T is a power of 1000.
+const SIZE_UNITS: [(&str, f64); 13] = [
+    ("b", 1.0),
+    ("k", 1e3),
+    ("kb", 1e3),
+    ("m", 1e6),
+    ("mb", 1e6),
+    ("g", 1e9),
+    ("gb", 1e9),
+    ("t", 1e12),
+    ("tb", 1e12),
+    ("kib", 1024.0),
+    ("mib", 1048576.0),
+    ("gib", 1073741824.0),
+    ("tib", 1099511627776.0),
+];
+
+/// Multipliers for duration suffixes, in seconds, shared by every option and --where.
+const DURATION_UNITS: [(&str, f64); 11] = [
+    ("ms", 0.001),
+    ("s", 1.0),
+    ("sec", 1.0),
+    ("m", 60.0),
+    ("min", 60.0),
+    ("h", 3600.0),
+    ("hr", 3600.0),
+    ("d", 86400.0),
+    ("day", 86400.0),
+    ("w", 604800.0),
+    ("week", 604800.0),
+];
+
+/// Returns the multiplier for a size suffix, e.g. 1024 for "KiB".
+pub fn size_unit(unit: &str) -> Option<f64> {
+    SIZE_UNITS
+        .iter()
+        .find(|(pattern, _)| unit.eq_ignore_ascii_case(pattern))
+        .map(|&(_, multiplier)| multiplier)
+}
+
+/// Returns the number of seconds for a duration suffix, e.g. 3600 for "h".
+pub fn duration_unit(unit: &str) -> Option<f64> {
+    DURATION_UNITS
+        .iter()
+        .find(|(pattern, _)| unit.eq_ignore_ascii_case(pattern))
+        .map(|&(_, seconds)| seconds)
+}
+
+impl FromStr for HumanDuration {
+    type Err = InvalidDuration;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut rest = s.trim();
+
+        if rest.is_empty() {
+            return Err(InvalidDuration);
+        }
+
+        let mut seconds = 0.0;
+
+        // sequence of <number><unit> pairs, e.g. 1h30m
+        while !rest.is_empty() {
+            let num_len = rest
+                .find(|c: char| !(c.is_ascii_digit() || c == '.'))
+                .unwrap_or(rest.len());
+            let value: f64 = rest[..num_len].parse().map_err(|_| InvalidDuration)?;
+
+            rest = rest[num_len..].trim_start();
+
+            let unit_len = rest.find(|c: char| !c.is_ascii_alphabetic()).unwrap_or(rest.len());
+            let unit = &rest[..unit_len];
+
+            rest = rest[unit_len..].trim_start();
+
+            seconds += value
+                * match unit {
+                    "" => 1.0,
+                    _ => duration_unit(unit).ok_or(InvalidDuration)?,
+                };
+        }
+
+        std::time::Duration::try_from_secs_f64(seconds)
+            .map(HumanDuration)
+            .map_err(|_| InvalidDuration)
+    }
+}
+
+impl FromStr for ByteSize {
+    type Err = InvalidByteSize;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+
+        let num_len = s.find(|c: char| !(c.is_ascii_digit() || c == '.')).unwrap_or(s.len());
+        let value: f64 = s[..num_len].parse().map_err(|_| InvalidByteSize)?;
+
+        let multiplier = match s[num_len..].trim_start() {
+            "" => 1.0,
+            unit => size_unit(unit).ok_or(InvalidByteSize)?,
+        };
+
+        Ok(ByteSize((value * multiplier) as u64))
+    }
+}
+
+impl FromStr for DetectMethod {
+    type Err = InvalidDetectMethod;
 
     fn from_str(s: &str) -> Result<Self, Self::Err> {
-        const PATTERNS: [(&str, FileType); 15] = [
-            ("jxl", FileType::JXL),
-            ("ppm", FileType::PPM),
-            ("pnm", FileType::PNM),
-            ("pfm", FileType::PFM),
-            ("pam", FileType::PAM),
-            ("pgx", FileType::PGX),
-            ("png", FileType::PNG),
-            ("apng", FileType::APNG),
-            ("gif", FileType::GIF),
-            ("jpeg", FileType::JPEG),
-            ("jpg", FileType::JPEG),
-            ("tiff", FileType::TIFF),
-            ("tif", FileType::TIFF),
-            ("tga", FileType::TGA),
-            ("qoi", FileType::QOI),
+        const PATTERNS: [(&str, DetectMethod); 4] = [
+            ("extension", DetectMethod::Extension),
+            ("ext", DetectMethod::Extension),
+            ("content", DetectMethod::Content),
+            ("magic", DetectMethod::Content),
         ];
 
-        for (pattern, ftype) in PATTERNS {
+        for (pattern, method) in PATTERNS {
+            if s.eq_ignore_ascii_case(pattern) {
+                return Ok(method);
+            }
+        }
+
+        Err(InvalidDetectMethod)
+    }
+}
+
+impl FromStr for Sample {
+    type Err = InvalidSample;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+
+        match s.strip_suffix('%') {
+            Some(percent) => match percent.trim().parse::<f64>() {
+                Ok(p) if (0.0..=100.0).contains(&p) => Ok(Sample::Fraction(p / 100.0)),
+                _ => Err(InvalidSample),
+            },
+            None => s.parse().map(Sample::Count).map_err(|_| InvalidSample),
+        }
+    }
+}
+
+impl FromStr for HardlinkPolicy {
+    type Err = InvalidHardlinkPolicy;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        const PATTERNS: [(&str, HardlinkPolicy); 3] = [
+            ("once", HardlinkPolicy::Once),
+            ("skip", HardlinkPolicy::Skip),
+            ("all", HardlinkPolicy::All),
+        ];
+
+        for (pattern, policy) in PATTERNS {
+            if s.eq_ignore_ascii_case(pattern) {
+                return Ok(policy);
+            }
+        }
+
+        Err(InvalidHardlinkPolicy)
+    }
+}
+
+impl FromStr for ReportFormat {
+    type Err = InvalidReportFormat;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        const PATTERNS: [(&str, ReportFormat); 2] = [("json", ReportFormat::Json), ("csv", ReportFormat::Csv)];
+
+        for (pattern, format) in PATTERNS {
+            if s.eq_ignore_ascii_case(pattern) {
+                return Ok(format);
+            }
+        }
+
+        Err(InvalidReportFormat)
+    }
+}
+
+/// Built-in mapping of file extensions to file types, also used to parse file type names.
+pub const EXTENSIONS: &[(&str, FileType)] = &[
+    ("jxl", FileType::JXL),
+    ("ppm", FileType::PPM),
+    ("pnm", FileType::PNM),
+    ("pfm", FileType::PFM),
+    ("pam", FileType::PAM),
+    ("pgx", FileType::PGX),
+    ("png", FileType::PNG),
+    ("apng", FileType::APNG),
+    ("gif", FileType::GIF),
+    ("jpeg", FileType::JPEG),
+    ("jpg", FileType::JPEG),
+    ("tiff", FileType::TIFF),
+    ("tif", FileType::TIFF),
+    ("tga", FileType::TGA),
+    ("qoi", FileType::QOI),
+    ("bmp", FileType::BMP),
+    ("zip", FileType::ZIP),
+    ("cbz", FileType::ZIP),
+];
+
+impl FromStr for FileType {
+    type Err = InvalidFileType;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        for &(pattern, ftype) in EXTENSIONS {
             if s.eq_ignore_ascii_case(pattern) {
                 return Ok(ftype);
             }
@@ -416,6 +1056,7 @@ impl Display for FileType {
             FileType::TGA => "tga",
             FileType::QOI => "qoi",
             FileType::BMP => "bmp",
+            FileType::ZIP => "zip",
         })
     }
 }
@@ -426,6 +1067,18 @@ impl FileType {
     pub const fn needs_conversion(self) -> bool {
         matches!(self, FileType::TIFF | FileType::TGA | FileType::QOI | FileType::BMP)
     }
+
+    /// Returns true if a file named with this type can hold content of the other type
+    /// without it being considered a mismatch, e.g. an animated PNG in a `.png` file.
+    pub fn is_compatible(self, other: FileType) -> bool {
+        self == other
+            || matches!(
+                (self, other),
+                (FileType::PNG | FileType::APNG, FileType::PNG | FileType::APNG)
+                    | (FileType::PNM | FileType::PPM, FileType::PNM | FileType::PPM)
+                    | (FileType::PNM, FileType::PAM | FileType::PFM)
+            )
+    }
 }
 
 impl FromStr for FileTypes {
@@ -439,6 +1092,7 @@ impl FromStr for FileTypes {
 
             if part == "*" {
                 let had_jxl = set.contains(&FileType::JXL);
+                let had_zip = set.contains(&FileType::ZIP);
 
                 set.extend(FileType::all());
 
@@ -446,6 +1100,13 @@ impl FromStr for FileTypes {
                     // if * was specified, don't include JXL itself unless it was explicitly requested
                     set.remove(&FileType::JXL);
                 }
+
+                if !had_zip {
+                    // archives are rewritten in place, so they also have to be asked for by name
+                    set.remove(&FileType::ZIP);
+                }
+
+                continue;
             }
 
             set.insert(part.parse::<FileType>()?);
@@ -454,3 +1115,106 @@ impl FromStr for FileTypes {
         Ok(FileTypes(set))
     }
 }
+
+/// Mapping of file extensions to file types, starting with the built-in [`EXTENSIONS`]
+/// and extended by user-defined aliases.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ExtensionMap(pub Vec<(String, FileType)>);
+
+impl Default for ExtensionMap {
+    fn default() -> Self {
+        ExtensionMap(EXTENSIONS.iter().map(|&(ext, ftype)| (ext.to_owned(), ftype)).collect())
+    }
+}
+
+impl ExtensionMap {
+    /// Looks up the file type for the given extension, ignoring case.
+    pub fn get(&self, ext: &str) -> Option<FileType> {
+        // user-defined entries are inserted at the front, so they take precedence
+        self.0
+            .iter()
+            .find(|(pattern, _)| ext.eq_ignore_ascii_case(pattern))
+            .map(|&(_, ftype)| ftype)
+    }
+}
+
+impl FromStr for ExtensionMap {
+    type Err = InvalidExtensionMap;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut map = ExtensionMap::default();
+
+        // comma-separated list of ext=type pairs
+        for part in s.split(',') {
+            let Some((ext, ftype)) = part.split_once('=') else {
+                return Err(InvalidExtensionMap);
+            };
+
+            let ext = ext.trim().trim_start_matches('.');
+
+            if ext.is_empty() {
+                return Err(InvalidExtensionMap);
+            }
+
+            let ftype = ftype.trim().parse::<FileType>().map_err(|_| InvalidExtensionMap)?;
+
+            map.0.insert(0, (ext.to_owned(), ftype));
+        }
+
+        Ok(map)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+
+    fn duration(s: &str) -> Option<Duration> {
+        s.parse::<HumanDuration>().ok().map(|HumanDuration(d)| d)
+    }
+
+    #[test]
+    fn durations() {
+        assert_eq!(duration("90"), Some(Duration::from_secs(90)));
+        assert_eq!(duration("500ms"), Some(Duration::from_millis(500)));
+        assert_eq!(duration("10m"), Some(Duration::from_secs(600)));
+        assert_eq!(duration("1h30m"), Some(Duration::from_secs(5400)));
+        assert_eq!(duration("1h 30min"), Some(Duration::from_secs(5400)));
+        assert_eq!(duration("1.5h"), Some(Duration::from_secs(5400)));
+        assert_eq!(duration("2D"), Some(Duration::from_secs(172800)));
+        assert_eq!(duration("1w"), Some(Duration::from_secs(604800)));
+        assert_eq!(duration(" 0s "), Some(Duration::ZERO));
+    }
+
+    #[test]
+    fn invalid_durations() {
+        assert_eq!(duration(""), None);
+        assert_eq!(duration("h"), None);
+        assert_eq!(duration("10x"), None);
+        assert_eq!(duration("-5s"), None);
+        assert_eq!(duration("1..5s"), None);
+        assert_eq!(duration("1e400s"), None);
+    }
+
+    #[test]
+    fn byte_sizes() {
+        let size = |s: &str| s.parse::<ByteSize>().ok().map(|ByteSize(n)| n);
+
+        assert_eq!(size("512"), Some(512));
+        assert_eq!(size("500GB"), Some(500_000_000_000));
+        assert_eq!(size("2 TiB"), Some(2 << 40));
+        assert_eq!(size("1.5m"), Some(1_500_000));
+        assert_eq!(size("10min"), None);
+        assert_eq!(size("GB"), None);
+    }
+
+    #[test]
+    fn time_fields() {
+        assert_eq!("mtime".parse::<TimeField>().ok(), Some(TimeField::MTime));
+        assert_eq!("CTIME".parse::<TimeField>().ok(), Some(TimeField::CTime));
+        assert_eq!("created".parse::<TimeField>().ok(), Some(TimeField::BTime));
+        assert!("atime".parse::<TimeField>().is_err());
+    }
+}
@@ -6,49 +6,120 @@ pub mod formatting;
 pub mod pool;
 pub mod utils;
 
-use std::{io::Result, thread::sleep, time::Duration};
+use std::{
+    io::{IsTerminal, Result},
+    thread::sleep,
+    time::Duration,
+};
 
 use crossterm::event;
 
-use crate::app::scan::ScanObserver;
+use crate::app::App2;
 
 fn main() -> Result<()> {
     let mut args: cli::Conv2JxlArgs = argh::from_env();
 
+    let command = args.command.take();
+
+    // paths given after the subcommand add to those given before it
+    match command {
+        Some(cli::Command::Estimate(ref estimate)) => args.paths.extend(estimate.paths.iter().cloned()),
+        Some(cli::Command::Restore(ref restore)) => args.paths.extend(restore.paths.iter().cloned()),
+        None => {}
+    }
+
     args.normalize();
 
-    let state = args.scan(&ScanObserver::default()).expect("Failed to scan files");
+    #[cfg(not(unix))]
+    if args.time_field == cli::TimeField::CTime || args.sort.iter().any(|&(key, _)| key == cli::SortMethod::CTime) {
+        eprintln!("ctime is only available on unix, use btime for the creation time");
+        std::process::exit(1);
+    }
 
-    let mut terminal = ratatui::init();
+    #[cfg(not(unix))]
+    if args.sort.iter().any(|&(key, _)| key == cli::SortMethod::Inode) {
+        eprintln!("sorting by inode is only available on unix");
+        std::process::exit(1);
+    }
 
-    terminal.clear()?;
+    // device numbers aren't available elsewhere, so there would be no way to tell filesystems apart
+    #[cfg(not(unix))]
+    if args.one_file_system {
+        eprintln!("--one-file-system is only available on unix");
+        std::process::exit(1);
+    }
+
+    // link counts aren't available elsewhere, so every link looks like a separate file
+    #[cfg(not(unix))]
+    if args.hardlinks != cli::HardlinkPolicy::Once {
+        eprintln!("--hardlinks is only available on unix");
+        std::process::exit(1);
+    }
 
-    let mut app = app::App {
-        ui_state: app::ConvertingUIState {
-            list_offset: 0,
-            last_processing: vec![usize::MAX; args.parallel as usize],
-            time: 0,
+    if let Some(cli::Command::Estimate(estimate)) = command {
+        if let Err(e) = app::estimate::run(args, &estimate) {
+            eprintln!("Failed to estimate: {e}");
+            std::process::exit(1);
+        }
+
+        return Ok(());
+    }
 
-            file_tab: app::FileTab::Files,
-            details: false,
+    if let Some(cli::Command::Restore(restore)) = command {
+        match app::restore::run(args, &restore) {
+            Ok(true) => return Ok(()),
+            Ok(false) => std::process::exit(1),
+            Err(e) => {
+                eprintln!("Failed to restore: {e}");
+                std::process::exit(1);
+            }
+        }
+    }
+
+    let journal = match args.journal {
+        Some(ref path) => match app::journal::Journal::open(path, &args) {
+            Ok(journal) => Some(journal),
+            Err(e) => {
+                eprintln!("Failed to open journal '{}': {e}", path.display());
+                std::process::exit(1);
+            }
         },
+        None => None,
+    };
 
-        shared: std::sync::Arc::new(app::SharedState {
-            args,
-            conv: state,
-            start: std::time::Instant::now(),
-        }),
+    let error_log = match args.error_log {
+        Some(ref path) => match app::error_log::ErrorLog::open(path, args.error_log_all) {
+            Ok(error_log) => Some(error_log),
+            Err(e) => {
+                eprintln!("Failed to open error log '{}': {e}", path.display());
+                std::process::exit(1);
+            }
+        },
+        None => None,
     };
 
-    let mut threads = Vec::new();
+    // without a terminal there is nothing to draw on or read keys from, as under cron or in a pipeline
+    if args.no_tui || !std::io::stdin().is_terminal() || !std::io::stdout().is_terminal() {
+        match app::headless::run(App2::Started(args).start_scan(journal, error_log)) {
+            Ok(code) => std::process::exit(code),
+            Err(e) => {
+                eprintln!("{e}");
+                std::process::exit(1);
+            }
+        }
+    }
+
+    let mut terminal = ratatui::init();
 
-    for i in 0..app.shared.args.parallel {
-        let shared = app.shared.clone();
+    terminal.clear()?;
 
-        threads.push(std::thread::spawn(move || {
-            shared.run(i as usize);
-        }));
-    }
+    let mut app = App2::Started(args).start_scan(journal, error_log);
+
+    // when streaming, conversion starts right away
+    let mut threads = match app {
+        App2::Converting(ref app) => app.spawn_workers(),
+        _ => Vec::new(),
+    };
 
     let mut stopped = 0;
 
@@ -82,40 +153,66 @@ fn main() -> Result<()> {
                 event::Event::FocusLost => {
                     sleep_time = IDLE_FRAME_TIME;
                 }
-                event::Event::Key(key) => match key.code {
-                    event::KeyCode::Char('q' | 'Q') | event::KeyCode::Esc => {
-                        stopped += 1;
-
-                        if stopped >= 2 {
-                            break; // kill immediately if already stopping
+                event::Event::Key(key) => match &mut app {
+                    // nothing to stop gracefully while scanning, so just quit
+                    App2::Started(_) | App2::Scanning { .. } => {
+                        if let event::KeyCode::Char('q' | 'Q') | event::KeyCode::Esc = key.code {
+                            break;
                         }
-
-                        app.shared.stop();
                     }
-                    event::KeyCode::Char('d' | 'D') => {
-                        app.ui_state.details = !app.ui_state.details;
-                    }
-                    event::KeyCode::PageUp => app.add_offset(-(size.height as i32 * 3 / 2 + 1)),
-                    event::KeyCode::PageDown => app.add_offset(size.height as i32 * 3 / 2 + 1),
-                    event::KeyCode::Up => app.add_offset(-1),
-                    event::KeyCode::Down => app.add_offset(1),
-                    event::KeyCode::Tab => {
-                        app.ui_state.list_offset = 0;
-
-                        if key.modifiers.contains(event::KeyModifiers::SHIFT) {
-                            app.ui_state.file_tab = app.ui_state.file_tab.prev();
-                        } else {
-                            app.ui_state.file_tab = app.ui_state.file_tab.next();
+                    App2::Converting(app) => match key.code {
+                        event::KeyCode::Char('q' | 'Q') | event::KeyCode::Esc => {
+                            stopped += 1;
+
+                            if stopped >= 2 {
+                                break; // kill immediately if already stopping
+                            }
+
+                            app.shared.stop();
                         }
-                    }
-                    event::KeyCode::Char(' ') => app.toggle_pause(),
-                    _ => {}
+                        event::KeyCode::Char('d' | 'D') => {
+                            app.ui_state.details = !app.ui_state.details;
+                        }
+                        event::KeyCode::PageUp => app.add_offset(-(size.height as i32 * 3 / 2 + 1)),
+                        event::KeyCode::PageDown => app.add_offset(size.height as i32 * 3 / 2 + 1),
+                        event::KeyCode::Up => app.add_offset(-1),
+                        event::KeyCode::Down => app.add_offset(1),
+                        event::KeyCode::Tab => {
+                            app.ui_state.list_offset = 0;
+
+                            if key.modifiers.contains(event::KeyModifiers::SHIFT) {
+                                app.ui_state.file_tab = app.ui_state.file_tab.prev();
+                            } else {
+                                app.ui_state.file_tab = app.ui_state.file_tab.next();
+                            }
+                        }
+                        event::KeyCode::Char(' ') => app.toggle_pause(),
+                        _ => {}
+                    },
                 },
                 _ => {}
             }
         }
 
-        if stopped > 0 && app.shared.conv.completed() {
+        if app.scan_finished() {
+            app = match app.finish_scan() {
+                Ok(app) => app,
+                Err(e) => {
+                    ratatui::restore();
+                    eprintln!("Failed to scan files: {e}");
+                    std::process::exit(1);
+                }
+            };
+
+            if let App2::Converting(ref app) = app {
+                threads = app.spawn_workers();
+            }
+        }
+
+        if stopped > 0
+            && let App2::Converting(ref app) = app
+            && app.shared.conv.completed()
+        {
             break;
         }
 
@@ -142,5 +239,22 @@ fn main() -> Result<()> {
 
     ratatui::restore();
 
+    if let App2::Converting(ref app) = app {
+        if let Some(ref path) = app.shared.args.report
+            && let Err(e) = app::report::write(&app.shared, path, app.shared.args.report_format)
+        {
+            eprintln!("Failed to write report '{}': {e}", path.display());
+            std::process::exit(1);
+        }
+
+        let summary = app.shared.summary(app.shared.start.elapsed().as_millis() as u64);
+
+        app.shared.print_final_summary(&summary, true);
+
+        if summary.errored > 0 {
+            std::process::exit(1);
+        }
+    }
+
     Ok(())
 }
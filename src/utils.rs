@@ -1 +1,286 @@
+use std::{
+    cmp::Ordering as CmpOrdering,
+    ffi::OsStr,
+    ops::Index,
+    sync::{
+        Mutex, OnceLock,
+        atomic::{AtomicUsize, Ordering},
+    },
+};
 
+/// log2 of the size of the first bucket in an [`AppendVec`]
+const FIRST_BUCKET_BITS: u32 = 6;
+const BUCKETS: usize = (usize::BITS - FIRST_BUCKET_BITS) as usize;
+
+/// An append-only vector that can be pushed to and read from concurrently.
+///
+/// Elements are stored in buckets of doubling size that are never reallocated,
+/// so references to elements remain valid for as long as the vector itself.
+pub struct AppendVec<T> {
+    buckets: [OnceLock<Box<[OnceLock<T>]>>; BUCKETS],
+    /// Number of elements visible to readers
+    len: AtomicUsize,
+    /// Serializes writers
+    push_lock: Mutex<()>,
+}
+
+impl<T> Default for AppendVec<T> {
+    fn default() -> Self {
+        AppendVec {
+            buckets: [const { OnceLock::new() }; BUCKETS],
+            len: AtomicUsize::new(0),
+            push_lock: Mutex::new(()),
+        }
+    }
+}
+
+impl<T> AppendVec<T> {
+    /// Returns the bucket index and offset within the bucket for the given index.
+    #[inline]
+    const fn locate(idx: usize) -> (usize, usize) {
+        let i = idx + (1 << FIRST_BUCKET_BITS);
+        let bucket = i.ilog2() - FIRST_BUCKET_BITS;
+
+        (bucket as usize, i - (1 << (bucket + FIRST_BUCKET_BITS)))
+    }
+
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.len.load(Ordering::Acquire)
+    }
+
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Appends an element, returning its index.
+    pub fn push(&self, value: T) -> usize {
+        let _guard = self.push_lock.lock().unwrap();
+
+        let idx = self.len.load(Ordering::Relaxed);
+        let (bucket, offset) = Self::locate(idx);
+
+        let bucket = self.buckets[bucket].get_or_init(|| {
+            (0..1usize << (bucket as u32 + FIRST_BUCKET_BITS))
+                .map(|_| OnceLock::new())
+                .collect()
+        });
+
+        let _ = bucket[offset].set(value);
+
+        // publish only after the element is initialized
+        self.len.store(idx + 1, Ordering::Release);
+
+        idx
+    }
+
+    pub fn get(&self, idx: usize) -> Option<&T> {
+        if idx >= self.len() {
+            return None;
+        }
+
+        let (bucket, offset) = Self::locate(idx);
+
+        self.buckets[bucket].get()?[offset].get()
+    }
+}
+
+impl<T> Index<usize> for AppendVec<T> {
+    type Output = T;
+
+    #[inline]
+    fn index(&self, idx: usize) -> &T {
+        self.get(idx).expect("index out of bounds")
+    }
+}
+
+/// Compares strings in natural order, where runs of digits are compared by their numeric value,
+/// so that "img2" comes before "img10". Ties, such as "01" and "1", are broken by plain byte order.
+pub fn natural_cmp(a: &OsStr, b: &OsStr) -> CmpOrdering {
+    let (a_bytes, b_bytes) = (a.as_encoded_bytes(), b.as_encoded_bytes());
+    let (mut a, mut b) = (a_bytes, b_bytes);
+
+    // splits off the leading run of digits, or a single other byte
+    fn split(s: &[u8]) -> (&[u8], &[u8]) {
+        let len = match s.iter().position(|c| !c.is_ascii_digit()) {
+            Some(0) => 1,
+            Some(n) => n,
+            None => s.len(),
+        };
+
+        s.split_at(len)
+    }
+
+    while !a.is_empty() && !b.is_empty() {
+        let ((a_part, a_rest), (b_part, b_rest)) = (split(a), split(b));
+
+        let ordering = match (a_part[0].is_ascii_digit(), b_part[0].is_ascii_digit()) {
+            (true, true) => {
+                let a_num = &a_part[a_part.iter().take_while(|&&c| c == b'0').count()..];
+                let b_num = &b_part[b_part.iter().take_while(|&&c| c == b'0').count()..];
+
+                // without leading zeros, longer numbers are larger
+                a_num.len().cmp(&b_num.len()).then_with(|| a_num.cmp(b_num))
+            }
+            _ => a_part.cmp(b_part),
+        };
+
+        if ordering.is_ne() {
+            return ordering;
+        }
+
+        (a, b) = (a_rest, b_rest);
+    }
+
+    a.len().cmp(&b.len()).then_with(|| a_bytes.cmp(b_bytes))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::thread;
+
+    use super::*;
+
+    fn cmp(a: &str, b: &str) -> CmpOrdering {
+        natural_cmp(OsStr::new(a), OsStr::new(b))
+    }
+
+    #[test]
+    fn natural_order() {
+        assert_eq!(cmp("img2", "img10"), CmpOrdering::Less);
+        assert_eq!(cmp("img10", "img2"), CmpOrdering::Greater);
+        assert_eq!(cmp("img2.png", "img2.png"), CmpOrdering::Equal);
+        assert_eq!(cmp("a2b3", "a2b10"), CmpOrdering::Less);
+        assert_eq!(cmp("img", "img1"), CmpOrdering::Less);
+        assert_eq!(cmp("9", "a"), CmpOrdering::Less);
+        assert_eq!(cmp("", "0"), CmpOrdering::Less);
+    }
+
+    #[test]
+    fn leading_zeros() {
+        assert_eq!(cmp("img007", "img10"), CmpOrdering::Less);
+        assert_eq!(cmp("img0010", "img9"), CmpOrdering::Greater);
+
+        // equal values are ordered by their bytes, so the order is still total
+        assert_eq!(cmp("img01", "img1"), CmpOrdering::Less);
+        assert_eq!(cmp("img1", "img01"), CmpOrdering::Greater);
+        assert_eq!(cmp("img001b", "img1a"), CmpOrdering::Greater);
+        assert_eq!(cmp("0", "00"), CmpOrdering::Less);
+    }
+
+    #[test]
+    fn long_digit_runs() {
+        // longer than a u64 can hold
+        let big = "123456789012345678901234567890";
+        let bigger = "123456789012345678901234567891";
+
+        assert_eq!(cmp(big, bigger), CmpOrdering::Less);
+        assert_eq!(cmp(&format!("x{big}"), "x99999999999999999999"), CmpOrdering::Greater);
+        assert_eq!(cmp(&format!("0000{big}"), big), CmpOrdering::Less);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn non_utf8() {
+        use std::os::unix::ffi::OsStrExt;
+
+        let cmp = |a: &[u8], b: &[u8]| natural_cmp(OsStr::from_bytes(a), OsStr::from_bytes(b));
+
+        assert_eq!(cmp(b"\xff2", b"\xff10"), CmpOrdering::Less);
+        assert_eq!(cmp(b"a\xfe", b"a\xff"), CmpOrdering::Less);
+        assert_eq!(cmp(b"\xff", b"\xff"), CmpOrdering::Equal);
+    }
+
+    #[test]
+    fn locate_bucket_boundaries() {
+        let locate = AppendVec::<()>::locate;
+
+        assert_eq!(locate(0), (0, 0));
+        assert_eq!(locate(63), (0, 63));
+        assert_eq!(locate(64), (1, 0));
+        assert_eq!(locate(191), (1, 127));
+        assert_eq!(locate(192), (2, 0));
+        assert_eq!(locate(447), (2, 255));
+        assert_eq!(locate(448), (3, 0));
+
+        // every index maps to a distinct slot, in order
+        let mut expected = (0, 0);
+
+        for idx in 0..10_000 {
+            assert_eq!(locate(idx), expected, "index {idx}");
+
+            expected.1 += 1;
+            if expected.1 == 1 << (expected.0 as u32 + FIRST_BUCKET_BITS) {
+                expected = (expected.0 + 1, 0);
+            }
+        }
+    }
+
+    #[test]
+    fn push_and_get() {
+        let vec = AppendVec::default();
+        assert!(vec.is_empty());
+        assert_eq!(vec.get(0), None);
+
+        for i in 0..200 {
+            assert_eq!(vec.push(i), i);
+        }
+
+        assert_eq!(vec.len(), 200);
+        assert_eq!(vec[63], 63);
+        assert_eq!(vec[64], 64);
+        assert_eq!(vec[127], 127);
+        assert_eq!(vec[128], 128);
+        assert_eq!(vec[199], 199);
+        assert_eq!(vec.get(200), None);
+    }
+
+    #[test]
+    fn concurrent_push_and_get() {
+        const THREADS: usize = 8;
+        const PER_THREAD: usize = 2_000;
+
+        let vec = AppendVec::default();
+
+        thread::scope(|s| {
+            for t in 0..THREADS {
+                let vec = &vec;
+
+                s.spawn(move || {
+                    for i in 0..PER_THREAD {
+                        let idx = vec.push((t, i));
+                        assert_eq!(vec[idx], (t, i));
+
+                        // everything published so far must be readable
+                        let len = vec.len();
+                        assert!(vec.get(len - 1).is_some());
+                    }
+                });
+            }
+
+            // readers racing the writers never see a missing element
+            s.spawn(|| {
+                while vec.len() < THREADS * PER_THREAD {
+                    let len = vec.len();
+                    for idx in len.saturating_sub(64)..len {
+                        assert!(vec.get(idx).is_some(), "index {idx}");
+                    }
+                }
+            });
+        });
+
+        assert_eq!(vec.len(), THREADS * PER_THREAD);
+
+        // each thread's elements are all present, in the order it pushed them
+        let mut next = [0; THREADS];
+
+        for idx in 0..vec.len() {
+            let (t, i) = vec[idx];
+            assert_eq!(i, next[t]);
+            next[t] += 1;
+        }
+
+        assert_eq!(next, [PER_THREAD; THREADS]);
+    }
+}